@@ -0,0 +1,150 @@
+//! Plays a game with no window and prints its turn profile and final stats
+//! as JSON, for balance and performance testing.
+//!
+//! Usage: simulate [--seed N] [--turns N] [--db PATH] [--replay SAVE]
+//!                 [--storage memory|disk]
+//!
+//! The player moves at random unless `--replay` is given, in which case the
+//! commands recorded in that save are played back instead. The game is run
+//! in memory and saved to `--db` at the end, unless `--storage disk` has it
+//! written there every turn, to compare the two.
+
+use anyhow::Context;
+use rand::Rng;
+use rust_roguelike::{component, map_gen, meta, profiler, Game, Recording, StepResult, Storage};
+
+const DEFAULT_TURNS: i64 = 100;
+const DEFAULT_DB: &str = "simulate.db";
+
+struct Args {
+    seed: Option<u64>,
+    turns: i64,
+    db: String,
+    replay: Option<String>,
+    storage: Storage,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = Args {
+        seed: None,
+        turns: DEFAULT_TURNS,
+        db: DEFAULT_DB.to_string(),
+        replay: None,
+        storage: Storage::Memory,
+    };
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || {
+            argv.next()
+                .with_context(|| format!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--seed" => args.seed = Some(value()?.parse()?),
+            "--turns" => args.turns = value()?.parse()?,
+            "--db" => args.db = value()?,
+            "--replay" => args.replay = Some(value()?),
+            "--storage" => {
+                args.storage = match value()?.as_str() {
+                    "memory" => Storage::Memory,
+                    "disk" => Storage::Disk,
+                    storage => anyhow::bail!("unknown storage '{}'", storage),
+                }
+            }
+            _ => anyhow::bail!("unknown argument '{}'", arg),
+        }
+    }
+    Ok(args)
+}
+
+/// Mostly wanders, now and then trying stairs and picking things up
+fn random_command(input: &mut meta::GameRng) -> meta::Command {
+    match input.gen_range(0..20) {
+        0 => meta::Command::FollowTransition,
+        1 => meta::Command::PickUp,
+        2 => meta::Command::OpenDoor,
+        _ => {
+            let (dx, dy) = [(1, 0), (-1, 0), (0, 1), (0, -1)][input.gen_range(0..4)];
+            meta::Command::Move { dx, dy }
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let (mut game, mut script) = match args.replay {
+        Some(ref save) => {
+            let (mut game, commands) = Game::replay(save.as_str())?
+                .with_context(|| format!("'{}' has nothing to replay", save))?;
+            // A replay has no save file of its own, so it is given --db
+            meta::remove_save(&args.db)?;
+            game.path = Some(args.db.clone().into());
+            (game, Some(commands))
+        }
+        None => {
+            let seed = args.seed.unwrap_or_else(meta::new_seed);
+            let game = Game::new(
+                &args.db,
+                args.storage,
+                false,
+                seed,
+                map_gen::DefaultGenerator::new(),
+            )?;
+            (game, None::<Recording>)
+        }
+    };
+    let seed = component::metadata::seed(&game.db)?.context("game has no seed")?;
+    // Input comes from its own rng so it never disturbs the game's
+    let mut input = meta::seeded_rng(seed.wrapping_add(1));
+
+    let mut is_dead = false;
+    while component::player::turns_passed(&game.db)? < args.turns {
+        let command = match script {
+            Some(ref mut commands) => match commands.pop_front() {
+                Some((_, command)) => command,
+                None => break,
+            },
+            None => random_command(&mut input),
+        };
+        let result = game.step(command)?;
+        if let Some(err) = game.take_autosave_failure() {
+            eprintln!("Couldn't autosave: {:#}", err);
+        }
+        match result {
+            StepResult::Ignored | StepResult::Continue => {}
+            StepResult::Won => break,
+            StepResult::Died(_) => {
+                is_dead = true;
+                break;
+            }
+        }
+    }
+
+    game.save()?;
+
+    let db = &game.db;
+    let splits = profiler::load_splits(db)?
+        .into_iter()
+        .map(|split| {
+            serde_json::json!({
+                "turn": split.turn,
+                "split": split.split,
+                "ms": split.ms,
+            })
+        })
+        .collect::<Vec<_>>();
+    let report = serde_json::json!({
+        "seed": seed,
+        "storage": format!("{:?}", args.storage).to_lowercase(),
+        "turns": component::player::turns_passed(db)?,
+        "depth": component::player::depth(db)?,
+        "kills": component::player::kills(db)?,
+        "health": component::health::get(db, game.player)?.map(|(current, _)| current),
+        "dead": is_dead,
+        "won": game.has_won()?,
+        "cause_of_death": component::player::cause_of_death(db)?,
+        "actors": component::actor::count(db)?,
+        "splits": splits,
+    });
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
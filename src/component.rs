@@ -1,44 +1,101 @@
 use crate::entity;
 use crate::game_object;
+use crate::meta;
 
-use rusqlite::{named_params, params};
-
-pub fn create_tables(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-    player::create_table(db)?;
-    actor::create_table(db)?;
-    velocity::create_table(db)?;
-    ai::create_table(db)?;
-    collision::create_table(db)?;
-    collision::create_passable_tiles_view(db)?;
-    health::create_table(db)?;
-    transition::create_table(db)?;
-    Ok(())
-}
+use rusqlite::{named_params, params, OptionalExtension};
 
-pub mod player {
+/// Facts about the save itself rather than anything in the world
+pub mod metadata {
     use super::*;
 
-    pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-        db.execute_batch(
-            "CREATE TABLE IF NOT EXISTS Player (
-                entity INTEGER UNIQUE NOT NULL,
-                is_creative BOOLEAN,
-                turn INTEGER,
-                outstanding_turns INTEGER,
-                level TEXT,
-                FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
-            )",
-        )
+    pub const SEED: &str = "seed";
+    pub const RNG_STATE: &str = "rng_state";
+    pub const CHARACTER: &str = "character";
+    pub const DEPTH: &str = "depth";
+    pub const TURN: &str = "turn";
+    pub const LAST_PLAYED: &str = "last_played";
+
+    pub fn set<T: rusqlite::ToSql>(
+        db: &rusqlite::Connection,
+        key: &str,
+        value: T,
+    ) -> rusqlite::Result<()> {
+        db.execute(
+            "INSERT INTO Meta (key, value) VALUES (?, ?)
+            ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )?;
+        Ok(())
     }
 
+    pub fn get<T: rusqlite::types::FromSql>(
+        db: &rusqlite::Connection,
+        key: &str,
+    ) -> rusqlite::Result<Option<T>> {
+        db.query_row("SELECT value FROM Meta WHERE key = ?", [key], |row| {
+            row.get(0)
+        })
+        .optional()
+    }
+
+    /// Notes down who is playing, how far they've got and when, so the save
+    /// can be described without loading the game
+    pub fn update_save_info(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+        db.execute(
+            "INSERT INTO Meta (key, value)
+            SELECT :character, Name.name FROM Player JOIN Name ON Name.entity = Player.entity
+            UNION ALL SELECT :depth, CAST(level AS INTEGER) FROM Player
+            UNION ALL SELECT :turn, turn FROM Player
+            UNION ALL SELECT :last_played, datetime('now')
+            ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+            named_params! {
+                ":character": CHARACTER,
+                ":depth": DEPTH,
+                ":turn": TURN,
+                ":last_played": LAST_PLAYED,
+            },
+        )?;
+        Ok(())
+    }
+
+    // SQLite integers are signed, so seeds are stored bit-for-bit as i64
+    pub fn set_seed(db: &rusqlite::Connection, seed: u64) -> rusqlite::Result<()> {
+        set(db, SEED, seed as i64)
+    }
+
+    pub fn seed(db: &rusqlite::Connection) -> rusqlite::Result<Option<u64>> {
+        Ok(get::<i64>(db, SEED)?.map(|seed| seed as u64))
+    }
+
+    /// Saves exactly where the rng is in its sequence, so a reloaded game
+    /// rolls the same numbers the original session would have.
+    pub fn save_rng(db: &rusqlite::Connection, rng: &meta::GameRng) -> rusqlite::Result<()> {
+        let state = serde_json::to_string(rng)
+            .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+        set(db, RNG_STATE, state)
+    }
+
+    pub fn load_rng(db: &rusqlite::Connection) -> rusqlite::Result<Option<meta::GameRng>> {
+        let Some(state) = get::<String>(db, RNG_STATE)? else {
+            return Ok(None);
+        };
+        serde_json::from_str(&state).map(Some).map_err(|err| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+        })
+    }
+}
+
+pub mod player {
+    use super::*;
+
     pub fn set(
         db: &rusqlite::Connection,
         entity: entity::Entity,
         is_creative: bool,
     ) -> rusqlite::Result<()> {
         db.execute(
-            "INSERT INTO Player (entity, turn, outstanding_turns, level, is_creative)
-            VALUES (:entity, 0, 0, '0', :is_creative)",
+            "INSERT INTO Player (entity, turn, level, kills, is_creative)
+            VALUES (:entity, 0, '0', 0, :is_creative)",
             named_params! {":entity": entity, ":is_creative": is_creative},
         )?;
         Ok(())
@@ -46,31 +103,47 @@ pub mod player {
 
     pub fn pass_time(db: &rusqlite::Connection, turns: i64) -> rusqlite::Result<()> {
         db.execute(
-            "UPDATE Player
-                SET turn = turn + :turns,
-                    outstanding_turns = max(outstanding_turns - :turns, 0)",
+            "UPDATE Player SET turn = turn + :turns",
             named_params! {":turns": turns},
         )?;
         Ok(())
     }
 
-    pub fn schedule_time(db: &rusqlite::Connection, turns: i64) -> rusqlite::Result<()> {
-        db.execute(
-            "UPDATE Player
-                SET outstanding_turns =outstanding_turns + :turns",
-            named_params! {":turns": turns},
-        )?;
-        Ok(())
+    pub fn turns_passed(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
+        db.query_row("SELECT turn FROM Player LIMIT 1", (), |row| row.get(0))
     }
 
-    pub fn outstanding_turns(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
-        db.query_row("SELECT outstanding_turns FROM Player LIMIT 1", (), |row| {
-            row.get(0)
-        })
+    pub fn level(db: &rusqlite::Connection) -> rusqlite::Result<String> {
+        db.query_row("SELECT level FROM Player LIMIT 1", (), |row| row.get(0))
     }
 
-    pub fn turns_passed(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
-        db.query_row("SELECT turn FROM Player LIMIT 1", (), |row| row.get(0))
+    pub fn depth(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
+        db.query_row(
+            "SELECT CAST(level AS INTEGER) FROM Player LIMIT 1",
+            (),
+            |row| row.get(0),
+        )
+    }
+
+    pub fn is_dead(db: &rusqlite::Connection) -> rusqlite::Result<bool> {
+        db.query_row(
+            "SELECT COALESCE(Health.current <= 0, FALSE)
+            FROM Player
+            LEFT JOIN Health ON Health.entity = Player.entity
+            LIMIT 1",
+            (),
+            |row| row.get(0),
+        )
+    }
+
+    pub fn kills(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
+        db.query_row("SELECT kills FROM Player LIMIT 1", (), |row| row.get(0))
+    }
+
+    pub fn cause_of_death(db: &rusqlite::Connection) -> rusqlite::Result<Option<String>> {
+        db.query_row("SELECT cause_of_death FROM Player LIMIT 1", (), |row| {
+            row.get(0)
+        })
     }
 
     pub fn is_creative(db: &rusqlite::Connection) -> rusqlite::Result<bool> {
@@ -80,6 +153,24 @@ pub mod player {
     }
 }
 
+pub mod name {
+    use super::*;
+
+    pub fn set(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        name: &str,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Name (entity, name)
+            VALUES (?, ?)
+            ON CONFLICT (entity) DO UPDATE SET name = excluded.name",
+        )?
+        .execute(params![entity, name])?;
+        Ok(())
+    }
+}
+
 pub mod actor {
     use super::*;
 
@@ -90,34 +181,14 @@ pub mod actor {
         pub pos: game_object::WorldPoint,
         pub color: game_object::Color,
         pub plane: game_object::Plane,
-    }
-
-    pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-        db.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS Actor (
-                entity INTEGER NOT NULL,
-                tile TEXT,
-                x INTEGER,
-                y INTEGER,
-                r INTEGER,
-                g INTEGER,
-                B INTEGER,
-                plane INTEGER,
-                FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
-            );
-            CREATE UNIQUE INDEX IF NOT EXISTS idx_actor_entity ON Actor (entity ASC);
-            CREATE INDEX IF NOT EXISTS idx_actor_plane ON Actor (plane DESC);
-            CREATE INDEX IF NOT EXISTS idx_actor_position ON Actor (x ASC, y ASC);
-        ",
-        )
+        pub level: String,
     }
 
     pub fn set(db: &rusqlite::Connection, actor: Actor) -> rusqlite::Result<()> {
         db.execute(
-            "INSERT INTO Actor (entity, tile, x, y, r, g, b, plane)
-            VALUES (:entity, :tile, :x, :y, :r, :g, :b, :plane)
-            ON CONFLICT (entity) DO UPDATE SET tile = excluded.tile, x = excluded.x, y = excluded.y, plane = excluded.plane",
+            "INSERT INTO Actor (entity, tile, x, y, r, g, b, plane, level)
+            VALUES (:entity, :tile, :x, :y, :r, :g, :b, :plane, :level)
+            ON CONFLICT (entity) DO UPDATE SET tile = excluded.tile, x = excluded.x, y = excluded.y, plane = excluded.plane, level = excluded.level",
             named_params![
                 ":entity": actor.entity,
                 ":tile": actor.tile,
@@ -127,6 +198,7 @@ pub mod actor {
                 ":g": actor.color.g,
                 ":b": actor.color.b,
                 ":plane": actor.plane,
+                ":level": actor.level,
             ],
         )?;
         Ok(())
@@ -140,12 +212,13 @@ pub mod actor {
         plane: game_object::Plane,
     ) -> rusqlite::Result<()> {
         db.prepare_cached(
-            "INSERT INTO Actor (entity, tile, x, y, r, g, b, plane)
-            SELECT :entity, :tile, x, y, :r, :g, :b, :plane
+            "INSERT INTO Actor (entity, tile, x, y, r, g, b, plane, level)
+            SELECT :entity, :tile, x, y, :r, :g, :b, :plane, level
             FROM Actor
             WHERE Actor.entity IN (SELECT entity FROM PassableTiles)
             ORDER BY pcg_random()
-            LIMIT 1"
+            LIMIT 1
+            ON CONFLICT (entity) DO UPDATE SET tile = excluded.tile, x = excluded.x, y = excluded.y, plane = excluded.plane, level = excluded.level"
         )?.execute(
             named_params![":entity": entity, ":tile": tile, ":r": color.r, ":g": color.g, ":b": color.b, ":plane": plane],
         )?;
@@ -157,6 +230,8 @@ pub mod actor {
             "
             SELECT *, min(plane)
             FROM Actor
+            WHERE level = (SELECT level FROM Player)
+            AND (x, y) IN (SELECT x, y FROM Visible)
             GROUP BY x, y",
         )?;
         let result = query
@@ -169,18 +244,61 @@ pub mod actor {
                 let g: u8 = row.get("g")?;
                 let b: u8 = row.get("b")?;
                 let plane: game_object::Plane = row.get("plane")?;
+                let level: String = row.get("level")?;
                 Ok(Actor {
                     entity,
                     tile,
                     pos: game_object::WorldPoint { x, y },
                     color: game_object::Color { r, g, b },
                     plane,
+                    level,
                 })
             })?
             .collect::<rusqlite::Result<Vec<Actor>>>()?;
         Ok(result)
     }
 
+    /// Finds where the staircase on `level` that leads to `destination` is,
+    /// or `None` if that level hasn't been generated yet.
+    pub fn find_transition(
+        db: &rusqlite::Connection,
+        level: &str,
+        destination: &str,
+    ) -> rusqlite::Result<Option<game_object::WorldPoint>> {
+        db.query_row(
+            "SELECT x, y
+            FROM Actor
+            JOIN Transition ON Transition.entity = Actor.entity
+            WHERE Actor.level = :level AND Transition.level = :destination
+            LIMIT 1",
+            named_params! {":level": level, ":destination": destination},
+            |row| {
+                Ok(game_object::WorldPoint {
+                    x: row.get(0)?,
+                    y: row.get(1)?,
+                })
+            },
+        )
+        .optional()
+    }
+
+    pub fn get_position(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+    ) -> rusqlite::Result<Option<game_object::WorldPoint>> {
+        db.query_row(
+            "SELECT x, y FROM Actor WHERE entity = ?",
+            params![entity],
+            |row| {
+                Ok(game_object::WorldPoint {
+                    x: row.get(0)?,
+                    y: row.get(1)?,
+                })
+            },
+        )
+        .optional()
+    }
+
     pub fn count(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
         db.query_row("SELECT COUNT(*) FROM Actor", (), |row| row.get(0))
     }
@@ -191,20 +309,6 @@ pub mod velocity {
 
     use super::*;
 
-    pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-        db.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS Velocity (
-                entity INTEGER NOT NULL,
-                dx INTEGER,
-                dy INTEGER,
-                FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
-            );
-            CREATE UNIQUE INDEX IF NOT EXISTS idx_velocity_entity ON Velocity (entity ASC);
-        ",
-        )
-    }
-
     pub fn set(
         db: &rusqlite::Connection,
         entity: entity::Entity,
@@ -238,39 +342,6 @@ pub mod velocity {
 pub mod collision {
     use super::*;
 
-    pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-        db.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS Collision (
-                entity INTEGER UNIQUE NOT NULL,
-                ground BOOLEAN,
-                solid BOOLEAN,
-                ephemeral BOOLEAN,
-                FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
-            )",
-        )
-    }
-
-    pub fn create_passable_tiles_view(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-        db.execute_batch(
-            "
-            CREATE VIEW IF NOT EXISTS PassableTiles AS
-            SELECT Collision.entity, Actor.x, Actor.y
-            FROM Collision
-            JOIN Actor ON Actor.entity = Collision.entity
-            WHERE Collision.ground = 1
-            AND Collision.entity NOT IN (
-                SELECT Collision.entity
-                FROM Collision
-                JOIN Actor ON Actor.entity = Collision.entity
-                JOIN Actor ground_actor ON ground_actor.x = Actor.x AND ground_actor.y = Actor.y
-                JOIN Collision ground_collision ON ground_collision.entity = ground_actor.entity
-                WHERE Collision.solid = 1 AND ground_collision.ground = 1
-            )
-        ",
-        )
-    }
-
     pub fn set(
         db: &rusqlite::Connection,
         entity: entity::Entity,
@@ -292,19 +363,6 @@ pub mod collision {
 pub mod health {
     use super::*;
 
-    pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-        db.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS Health (
-                entity INTEGER UNIQUE NOT NULL,
-                max INTEGER,
-                current INTEGER,
-                regen INTEGER,
-                FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
-            )",
-        )
-    }
-
     pub fn set(
         db: &rusqlite::Connection,
         entity: entity::Entity,
@@ -321,23 +379,64 @@ pub mod health {
         )?;
         Ok(())
     }
+
+    pub fn get(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+    ) -> rusqlite::Result<Option<(i64, i64)>> {
+        db.query_row(
+            "SELECT Health.current, Stats.max_health
+            FROM Health
+            JOIN Stats ON Stats.entity = Health.entity
+            WHERE Health.entity = ?",
+            params![entity],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+    }
 }
 
-pub mod ai {
+pub mod attack {
     use super::*;
 
-    pub const AI_TYPE_RANDOM: &str = "random";
+    pub fn set(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        power: i64,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Attack (entity, power)
+            VALUES (?, ?)
+            ON CONFLICT (entity) DO UPDATE SET power = excluded.power",
+        )?
+        .execute(params![entity, power])?;
+        Ok(())
+    }
+}
 
-    pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-        db.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS Ai (
-                entity INTEGER UNIQUE NOT NULL,
-                type TEXT,
-                FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
-            )",
-        )
+pub mod defense {
+    use super::*;
+
+    pub fn set(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        armor: i64,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Defense (entity, armor)
+            VALUES (?, ?)
+            ON CONFLICT (entity) DO UPDATE SET armor = excluded.armor",
+        )?
+        .execute(params![entity, armor])?;
+        Ok(())
     }
+}
+
+pub mod ai {
+    use super::*;
+
+    pub const AI_TYPE_RANDOM: &str = "random";
+    pub const AI_TYPE_CHASE: &str = "chase";
 
     pub fn set_random(db: &rusqlite::Connection, entity: entity::Entity) -> rusqlite::Result<()> {
         db.prepare_cached(
@@ -348,22 +447,164 @@ pub mod ai {
         .execute(named_params! {":entity": entity, ":type": AI_TYPE_RANDOM})?;
         Ok(())
     }
+
+    pub fn set_chase(db: &rusqlite::Connection, entity: entity::Entity) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Ai (entity, type)
+            VALUES (:entity, :type)
+            ON CONFLICT (entity) DO UPDATE SET type = excluded.type",
+        )?
+        .execute(named_params! {":entity": entity, ":type": AI_TYPE_CHASE})?;
+        Ok(())
+    }
+
+    /// All entities acting this phase with the given AI type, and where they are
+    pub fn get_acting(
+        db: &rusqlite::Connection,
+        ai_type: &str,
+    ) -> rusqlite::Result<Vec<(entity::Entity, game_object::WorldPoint)>> {
+        let mut query = db.prepare_cached(
+            "SELECT Ai.entity, Actor.x, Actor.y
+            FROM Ai
+            JOIN Actor ON Actor.entity = Ai.entity
+            WHERE Ai.type = :type
+            AND Ai.entity IN (SELECT entity FROM Acting)",
+        )?;
+        let result = query
+            .query_map(named_params! {":type": ai_type}, |row| {
+                Ok((
+                    row.get(0)?,
+                    game_object::WorldPoint {
+                        x: row.get(1)?,
+                        y: row.get(2)?,
+                    },
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(result)
+    }
 }
 
-pub mod transition {
+/// How often things get to act. Every turn each actor on the active level
+/// gains its speed in energy, and acts whenever it has enough to pay for it.
+pub mod energy {
     use super::*;
 
-    pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    /// What it costs a monster to act, and what a normal player action costs
+    pub const ACTION_COST: i64 = 100;
+
+    pub const SLOW_SPEED: i64 = 50;
+    pub const NORMAL_SPEED: i64 = 100;
+    pub const FAST_SPEED: i64 = 200;
+
+    pub fn set(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        speed: i64,
+        energy: i64,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Energy (entity, speed, energy)
+            VALUES (:entity, :speed, :energy)
+            ON CONFLICT (entity) DO UPDATE SET speed = excluded.speed, energy = excluded.energy",
+        )?
+        .execute(named_params! {":entity": entity, ":speed": speed, ":energy": energy})?;
+        Ok(())
+    }
+
+    /// Gives the entity one of the speeds at random, with no energy yet
+    pub fn set_random_speed(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        speeds: &[i64],
+    ) -> rusqlite::Result<()> {
+        let index: usize = db.query_row("SELECT pcg_randint(0, ?)", [speeds.len() - 1], |row| {
+            row.get(0)
+        })?;
+        set(db, entity, speeds[index], 0)
+    }
+
+    /// Whether the entity has the energy to act. Anything without an energy
+    /// component is always ready.
+    pub fn is_ready(db: &rusqlite::Connection, entity: entity::Entity) -> rusqlite::Result<bool> {
+        Ok(db
+            .query_row(
+                "SELECT energy >= :cost FROM Energy WHERE entity = :entity",
+                named_params! {":entity": entity, ":cost": ACTION_COST},
+                |row| row.get(0),
+            )
+            .optional()?
+            .unwrap_or(true))
+    }
+
+    pub fn spend(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        cost: i64,
+    ) -> rusqlite::Result<()> {
+        db.execute(
+            "UPDATE Energy SET energy = energy - :cost WHERE entity = :entity",
+            named_params! {":entity": entity, ":cost": cost},
+        )?;
+        Ok(())
+    }
+
+    /// Everything on the active level gets its speed's worth of energy
+    pub fn gain(db: &rusqlite::Connection) -> rusqlite::Result<()> {
         db.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS Transition (
-                entity INTEGER UNIQUE NOT NULL,
-                level TEXT,
-                FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
-            )",
+            "UPDATE Energy
+            SET energy = energy + speed
+            WHERE entity IN (SELECT entity FROM Actor WHERE level = (SELECT level FROM Player))",
         )
     }
 
+    /// Makes the entity the only one acting
+    pub fn set_acting(db: &rusqlite::Connection, entity: entity::Entity) -> rusqlite::Result<()> {
+        clear_acting(db)?;
+        db.execute("INSERT INTO Acting (entity) VALUES (?)", [entity])?;
+        Ok(())
+    }
+
+    /// Fills Acting with the monsters that act next: those that can afford
+    /// to act and have the most energy. The player acts on their own, when
+    /// given a command. Returns how many are acting.
+    pub fn select_next_acting(db: &rusqlite::Connection) -> rusqlite::Result<usize> {
+        clear_acting(db)?;
+        db.execute(
+            "WITH ready AS (
+                SELECT Energy.entity, Energy.energy
+                FROM Energy
+                JOIN Actor ON Actor.entity = Energy.entity
+                WHERE Actor.level = (SELECT level FROM Player)
+                AND Energy.entity NOT IN (SELECT entity FROM Player)
+                AND Energy.energy >= :cost
+            )
+            INSERT INTO Acting (entity)
+            SELECT entity FROM ready WHERE energy = (SELECT max(energy) FROM ready)",
+            named_params! {":cost": ACTION_COST},
+        )
+    }
+
+    /// Charges everything that just acted for its action
+    pub fn finish_acting(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+        db.execute(
+            "UPDATE Energy
+            SET energy = energy - :cost
+            WHERE entity IN (SELECT entity FROM Acting)",
+            named_params! {":cost": ACTION_COST},
+        )?;
+        clear_acting(db)
+    }
+
+    pub fn clear_acting(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+        db.execute("DELETE FROM Acting", ())?;
+        Ok(())
+    }
+}
+
+pub mod transition {
+    use super::*;
+
     pub fn set(
         db: &rusqlite::Connection,
         entity: entity::Entity,
@@ -378,3 +619,418 @@ pub mod transition {
         Ok(())
     }
 }
+
+pub mod door {
+    use super::*;
+
+    pub const OPEN_TILE: &str = "-";
+    pub const CLOSED_TILE: &str = "+";
+
+    pub fn set(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        open: bool,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Door (entity, open, toggle)
+            VALUES (?, ?, FALSE)
+            ON CONFLICT (entity) DO UPDATE SET open = excluded.open, toggle = excluded.toggle",
+        )?
+        .execute(params![entity, open])?;
+        Ok(())
+    }
+
+    /// Asks for every door next to `entity` that is open, if
+    /// `currently_open`, or closed otherwise, to be toggled on the next turn.
+    /// Returns how many there were.
+    pub fn toggle_adjacent(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        currently_open: bool,
+    ) -> rusqlite::Result<usize> {
+        db.prepare_cached(
+            "UPDATE Door
+            SET toggle = TRUE
+            FROM Actor door_actor, Actor
+            WHERE door_actor.entity = Door.entity
+            AND Actor.entity = :entity
+            AND door_actor.level = Actor.level
+            AND abs(door_actor.x - Actor.x) <= 1
+            AND abs(door_actor.y - Actor.y) <= 1
+            AND Door.open = :currently_open",
+        )?
+        .execute(named_params! {":entity": entity, ":currently_open": currently_open})
+    }
+}
+
+pub mod item {
+    use super::*;
+
+    pub fn set(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        tile: &str,
+        color: game_object::Color,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Item (entity, tile, r, g, b)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (entity) DO UPDATE SET tile = excluded.tile, r = excluded.r, g = excluded.g, b = excluded.b",
+        )?
+        .execute(params![entity, tile, color.r, color.g, color.b])?;
+        Ok(())
+    }
+}
+
+pub mod inventory {
+    use super::*;
+
+    /// Moves every item on the owner's tile into their inventory. Returns
+    /// how many items were picked up.
+    pub fn pick_up(db: &rusqlite::Connection, owner: entity::Entity) -> rusqlite::Result<usize> {
+        let picked_up = db.execute(
+            "INSERT INTO Inventory (owner, item)
+            SELECT owner_actor.entity, Item.entity
+            FROM Item
+            JOIN Actor item_actor ON item_actor.entity = Item.entity
+            JOIN Actor owner_actor
+                ON owner_actor.level = item_actor.level
+                AND owner_actor.x = item_actor.x
+                AND owner_actor.y = item_actor.y
+            WHERE owner_actor.entity = :owner",
+            named_params! {":owner": owner},
+        )?;
+        db.execute(
+            "DELETE FROM Actor WHERE entity IN (SELECT item FROM Inventory WHERE owner = :owner)",
+            named_params! {":owner": owner},
+        )?;
+        Ok(picked_up)
+    }
+
+    /// Puts an item back on the floor where its owner is standing. Returns
+    /// how many items were dropped, which is none if the owner doesn't have
+    /// it.
+    pub fn drop(
+        db: &rusqlite::Connection,
+        owner: entity::Entity,
+        item: entity::Entity,
+    ) -> rusqlite::Result<usize> {
+        let dropped = db.execute(
+            "INSERT INTO Actor (entity, tile, x, y, r, g, b, plane, level)
+            SELECT Item.entity, Item.tile, owner_actor.x, owner_actor.y, Item.r, Item.g, Item.b, :plane, owner_actor.level
+            FROM Inventory
+            JOIN Item ON Item.entity = Inventory.item
+            JOIN Actor owner_actor ON owner_actor.entity = Inventory.owner
+            WHERE Inventory.owner = :owner AND Inventory.item = :item",
+            named_params! {":owner": owner, ":item": item, ":plane": game_object::Plane::Objects},
+        )?;
+        db.execute(
+            "DELETE FROM Inventory WHERE owner = :owner AND item = :item",
+            named_params! {":owner": owner, ":item": item},
+        )?;
+        db.execute(
+            "DELETE FROM Equipped WHERE owner = :owner AND item = :item",
+            named_params! {":owner": owner, ":item": item},
+        )?;
+        Ok(dropped)
+    }
+
+    /// Everything the owner is carrying, with its name
+    pub fn get(
+        db: &rusqlite::Connection,
+        owner: entity::Entity,
+    ) -> rusqlite::Result<Vec<(entity::Entity, String)>> {
+        let mut query = db.prepare_cached(
+            "SELECT
+                Inventory.item,
+                COALESCE(Name.name, 'something') || COALESCE(' (' || Equipped.slot || ')', '')
+            FROM Inventory
+            LEFT JOIN Name ON Name.entity = Inventory.item
+            LEFT JOIN Equipped ON Equipped.item = Inventory.item
+            WHERE Inventory.owner = ?
+            ORDER BY Inventory.rowid",
+        )?;
+        let result = query
+            .query_map(params![owner], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(result)
+    }
+}
+
+pub mod effect {
+    use super::*;
+
+    pub const EFFECT_HEAL: &str = "heal";
+    pub const EFFECT_TELEPORT: &str = "teleport";
+    pub const EFFECT_AREA_DAMAGE: &str = "area_damage";
+
+    pub fn add(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        kind: &str,
+        magnitude: i64,
+        radius: i64,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Effect (entity, kind, magnitude, radius)
+            VALUES (?, ?, ?, ?)",
+        )?
+        .execute(params![entity, kind, magnitude, radius])?;
+        Ok(())
+    }
+
+    /// Applies every effect of `item` on behalf of `user`, then uses the item
+    /// up. Returns false if the item doesn't do anything.
+    pub fn apply(
+        db: &rusqlite::Connection,
+        user: entity::Entity,
+        item: entity::Entity,
+    ) -> rusqlite::Result<bool> {
+        let effects = db
+            .prepare_cached(
+                "SELECT EffectKind.query, Effect.magnitude, Effect.radius
+                FROM Effect
+                JOIN EffectKind ON EffectKind.name = Effect.kind
+                WHERE Effect.entity = ?",
+            )?
+            .query_map(params![item], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if effects.is_empty() {
+            return Ok(false);
+        }
+
+        for (query, magnitude, radius) in effects {
+            let mut statement = db.prepare_cached(&query)?;
+            // Effects only mention the parameters they need
+            let parameters: [(&str, &dyn rusqlite::ToSql); 3] = [
+                (":user", &user),
+                (":magnitude", &magnitude),
+                (":radius", &radius),
+            ];
+            for (name, value) in parameters {
+                if let Some(index) = statement.parameter_index(name)? {
+                    statement.raw_bind_parameter(index, value)?;
+                }
+            }
+            statement.raw_execute()?;
+        }
+        db.execute("DELETE FROM Entity WHERE id = ?", params![item])?;
+        Ok(true)
+    }
+}
+
+pub mod equipment {
+    use super::*;
+
+    pub const SLOT_WEAPON: &str = "weapon";
+    pub const SLOT_ARMOUR: &str = "armour";
+    pub const SLOT_RING: &str = "ring";
+
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct StatBonus {
+        pub attack: i64,
+        pub defense: i64,
+        pub max_health: i64,
+        pub regen: i64,
+    }
+
+    pub fn set(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        slot: &str,
+        bonus: StatBonus,
+    ) -> rusqlite::Result<()> {
+        db.prepare_cached(
+            "INSERT INTO Equipment (entity, slot, attack_bonus, defense_bonus, max_health_bonus, regen_bonus)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (entity) DO UPDATE SET
+                slot = excluded.slot,
+                attack_bonus = excluded.attack_bonus,
+                defense_bonus = excluded.defense_bonus,
+                max_health_bonus = excluded.max_health_bonus,
+                regen_bonus = excluded.regen_bonus",
+        )?
+        .execute(params![
+            entity,
+            slot,
+            bonus.attack,
+            bonus.defense,
+            bonus.max_health,
+            bonus.regen
+        ])?;
+        Ok(())
+    }
+
+    /// Takes the item off if the owner has it equipped, otherwise puts it
+    /// on in place of whatever was in its slot. Returns false if the item
+    /// can't be equipped at all.
+    pub fn toggle(
+        db: &rusqlite::Connection,
+        owner: entity::Entity,
+        item: entity::Entity,
+    ) -> rusqlite::Result<bool> {
+        let unequipped = db.execute(
+            "DELETE FROM Equipped WHERE owner = :owner AND item = :item",
+            named_params! {":owner": owner, ":item": item},
+        )?;
+        if unequipped > 0 {
+            return Ok(true);
+        }
+        let equipped = db.execute(
+            "INSERT INTO Equipped (owner, item, slot)
+            SELECT :owner, entity, slot
+            FROM Equipment
+            WHERE entity = :item
+            ON CONFLICT (owner, slot) DO UPDATE SET item = excluded.item",
+            named_params! {":owner": owner, ":item": item},
+        )?;
+        Ok(equipped > 0)
+    }
+}
+
+pub mod vision {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Replaces the tiles the player can see on the active level, and adds
+    /// what is on them to what the player remembers.
+    pub fn set_visible(
+        db: &rusqlite::Connection,
+        visible: &HashSet<game_object::WorldPoint>,
+    ) -> rusqlite::Result<()> {
+        db.execute("DELETE FROM Visible", ())?;
+        let mut insert = db.prepare_cached("INSERT INTO Visible (x, y) VALUES (?, ?)")?;
+        for pos in visible {
+            insert.execute(params![pos.x, pos.y])?;
+        }
+        db.execute(
+            "
+            -- Only the scenery is remembered, not whatever was passing through
+            INSERT INTO Remembered (level, x, y, tile)
+            SELECT level, x, y, tile
+            FROM (
+                SELECT Actor.level, Actor.x, Actor.y, Actor.tile, min(Actor.plane)
+                FROM Actor
+                JOIN Visible ON Visible.x = Actor.x AND Visible.y = Actor.y
+                WHERE Actor.level = (SELECT level FROM Player)
+                AND Actor.plane >= :plane
+                GROUP BY Actor.x, Actor.y
+            )
+            WHERE true
+            ON CONFLICT (level, x, y) DO UPDATE SET tile = excluded.tile",
+            named_params! {":plane": game_object::Plane::Objects},
+        )?;
+        Ok(())
+    }
+
+    /// Tiles on the active level the player has seen before but can't see now
+    pub fn get_remembered(
+        db: &rusqlite::Connection,
+    ) -> rusqlite::Result<Vec<(game_object::WorldPoint, String)>> {
+        let mut query = db.prepare_cached(
+            "SELECT x, y, tile
+            FROM Remembered
+            WHERE level = (SELECT level FROM Player)
+            AND (x, y) NOT IN (SELECT x, y FROM Visible)",
+        )?;
+        let result = query
+            .query_map((), |row| {
+                Ok((
+                    game_object::WorldPoint {
+                        x: row.get(0)?,
+                        y: row.get(1)?,
+                    },
+                    row.get(2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(result)
+    }
+
+    /// Tiles on the active level that block line of sight
+    pub fn get_opaque(
+        db: &rusqlite::Connection,
+    ) -> rusqlite::Result<HashSet<game_object::WorldPoint>> {
+        let mut query = db.prepare_cached(
+            "SELECT Actor.x, Actor.y
+            FROM Actor
+            JOIN Collision ON Collision.entity = Actor.entity
+            WHERE Actor.level = (SELECT level FROM Player)
+            AND Collision.solid
+            AND (
+                Actor.plane = :plane
+                OR Actor.entity IN (SELECT entity FROM Door WHERE NOT open)
+            )",
+        )?;
+        let result = query
+            .query_map(named_params! {":plane": game_object::Plane::Wall}, |row| {
+                Ok(game_object::WorldPoint {
+                    x: row.get(0)?,
+                    y: row.get(1)?,
+                })
+            })?
+            .collect::<rusqlite::Result<HashSet<_>>>()?;
+        Ok(result)
+    }
+}
+
+/// Every command the player has given, in order, so the game can be played
+/// back from its seed
+pub mod replay {
+    use super::*;
+    use meta::Command;
+
+    pub fn record(db: &rusqlite::Connection, turn: i64, command: Command) -> rusqlite::Result<()> {
+        let (name, dx, dy, item) = match command {
+            Command::Move { dx, dy } => ("move", Some(dx), Some(dy), None),
+            Command::Wait => ("wait", None, None, None),
+            Command::OpenDoor => ("open_door", None, None, None),
+            Command::CloseDoor => ("close_door", None, None, None),
+            Command::FollowTransition => ("follow_transition", None, None, None),
+            Command::PickUp => ("pick_up", None, None, None),
+            Command::Drop(item) => ("drop", None, None, Some(item)),
+            Command::Use(item) => ("use", None, None, Some(item)),
+        };
+        db.prepare_cached(
+            "INSERT INTO Replay (turn, command, dx, dy, item) VALUES (?, ?, ?, ?, ?)",
+        )?
+        .execute(params![turn, name, dx, dy, item])?;
+        Ok(())
+    }
+
+    /// Returns the recorded commands along with the turn each was given on
+    pub fn load(db: &rusqlite::Connection) -> rusqlite::Result<Vec<(i64, Command)>> {
+        let mut stmt = db.prepare("SELECT turn, command, dx, dy, item FROM Replay ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            let name = row.get::<usize, String>(1)?;
+            let command = match name.as_str() {
+                "move" => Command::Move {
+                    dx: row.get(2)?,
+                    dy: row.get(3)?,
+                },
+                "wait" => Command::Wait,
+                "open_door" => Command::OpenDoor,
+                "close_door" => Command::CloseDoor,
+                "follow_transition" => Command::FollowTransition,
+                "pick_up" => Command::PickUp,
+                "drop" => Command::Drop(row.get(4)?),
+                "use" => Command::Use(row.get(4)?),
+                _ => {
+                    return Err(rusqlite::Error::FromSqlConversionFailure(
+                        1,
+                        rusqlite::types::Type::Text,
+                        format!("unknown command '{}'", name).into(),
+                    ))
+                }
+            };
+            Ok((row.get(0)?, command))
+        })?;
+        rows.collect()
+    }
+}
@@ -3,8 +3,8 @@ use std::collections::HashSet;
 // BTerm shim layer
 use ggez::{glam, graphics, input::keyboard, GameResult};
 
-use crate::game_object::{self, Color};
-use crate::meta;
+use crate::ui;
+use rust_roguelike::game_object::{self, Color};
 
 pub const PIXEL_SIZE: f32 = 16.;
 pub const PIXEL_WIDTH: f32 = 8.475;
@@ -29,18 +29,18 @@ impl ConsolePoint {
 impl From<game_object::WorldPoint> for ConsolePoint {
     fn from(pos: game_object::WorldPoint) -> Self {
         ConsolePoint {
-            x: pos.x + meta::WORLD_TOP_LEFT.x,
-            y: pos.y + meta::WORLD_TOP_LEFT.y,
+            x: pos.x + ui::WORLD_TOP_LEFT.x,
+            y: pos.y + ui::WORLD_TOP_LEFT.y,
         }
     }
 }
 
 impl From<ggez::mint::Point2<f32>> for ConsolePoint {
     fn from(pos: ggez::mint::Point2<f32>) -> Self {
-        return ConsolePoint {
+        ConsolePoint {
             x: (pos.x / PIXEL_WIDTH) as i64,
             y: (pos.y / PIXEL_HEIGHT) as i64,
-        };
+        }
     }
 }
 
@@ -134,12 +134,14 @@ impl Console {
         );
         canvas.draw(
             &graphics::Quad,
-            graphics::DrawParam::new().dest_rect(bg_box).color(bg_color),
+            graphics::DrawParam::new()
+                .dest_rect(bg_box)
+                .color(ggez_color(bg_color)),
         );
 
         let fragment = graphics::TextFragment {
             text: s.to_owned(),
-            color: Some(fg_color.into()),
+            color: Some(ggez_color(fg_color)),
             ..Default::default()
         };
         canvas.draw(
@@ -159,3 +161,12 @@ impl Console {
         glam::Vec2::new(x as f32 * PIXEL_WIDTH, y as f32 * PIXEL_HEIGHT)
     }
 }
+
+fn ggez_color(color: Color) -> graphics::Color {
+    graphics::Color {
+        r: color.r as f32 / 255.0,
+        g: color.g as f32 / 255.0,
+        b: color.b as f32 / 255.0,
+        a: 1.0,
+    }
+}
@@ -6,7 +6,7 @@ pub struct Entity {
 }
 
 impl ToSql for Entity {
-    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         Ok(self.id.into())
     }
 }
@@ -19,10 +19,6 @@ impl FromSql for Entity {
     }
 }
 
-pub fn create_table(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-    db.execute_batch("CREATE TABLE Entity (id INTEGER PRIMARY KEY)")
-}
-
 pub fn create(db: &rusqlite::Connection) -> rusqlite::Result<Entity> {
     db.query_row("INSERT INTO Entity VALUES (NULL) RETURNING id", (), |row| {
         Ok(Entity { id: row.get(0)? })
@@ -32,9 +28,8 @@ pub fn create(db: &rusqlite::Connection) -> rusqlite::Result<Entity> {
 pub fn load_player(db: &rusqlite::Connection) -> rusqlite::Result<Entity> {
     db.query_row(
         "
-    SELECT id
-    FROM Entity
-    JOIN Player",
+    SELECT entity
+    FROM Player",
         [],
         |row| Ok(Entity { id: row.get(0)? }),
     )
@@ -0,0 +1,145 @@
+use std::collections::HashSet;
+
+use crate::game_object::WorldPoint;
+
+/// Symmetric shadowcasting, as described at
+/// https://www.albertford.com/shadowcasting/. Returns every tile visible from
+/// `origin`, including the opaque tiles that bound what can be seen.
+pub fn compute(origin: WorldPoint, is_opaque: impl Fn(WorldPoint) -> bool) -> HashSet<WorldPoint> {
+    let mut visible = HashSet::from([origin]);
+    for cardinal in [
+        Cardinal::North,
+        Cardinal::East,
+        Cardinal::South,
+        Cardinal::West,
+    ] {
+        let quadrant = Quadrant { cardinal, origin };
+        scan(
+            &quadrant,
+            Row {
+                depth: 1,
+                start_slope: Slope::new(-1, 1),
+                end_slope: Slope::new(1, 1),
+            },
+            &is_opaque,
+            &mut visible,
+        );
+    }
+    visible
+}
+
+fn scan(
+    quadrant: &Quadrant,
+    mut row: Row,
+    is_opaque: &impl Fn(WorldPoint) -> bool,
+    visible: &mut HashSet<WorldPoint>,
+) {
+    let mut previous_opaque = None;
+    for col in row.min_col()..=row.max_col() {
+        let pos = quadrant.transform(row.depth, col);
+        let opaque = is_opaque(pos);
+        if opaque || row.is_symmetric(col) {
+            visible.insert(pos);
+        }
+        if previous_opaque == Some(true) && !opaque {
+            row.start_slope = Slope::of_tile(row.depth, col);
+        }
+        if previous_opaque == Some(false) && opaque {
+            let mut next_row = row.next();
+            next_row.end_slope = Slope::of_tile(row.depth, col);
+            scan(quadrant, next_row, is_opaque, visible);
+        }
+        previous_opaque = Some(opaque);
+    }
+    if previous_opaque == Some(false) {
+        scan(quadrant, row.next(), is_opaque, visible);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Cardinal {
+    North,
+    East,
+    South,
+    West,
+}
+
+struct Quadrant {
+    cardinal: Cardinal,
+    origin: WorldPoint,
+}
+
+impl Quadrant {
+    fn transform(&self, depth: i64, col: i64) -> WorldPoint {
+        let WorldPoint { x, y } = self.origin;
+        match self.cardinal {
+            Cardinal::North => WorldPoint {
+                x: x + col,
+                y: y - depth,
+            },
+            Cardinal::South => WorldPoint {
+                x: x + col,
+                y: y + depth,
+            },
+            Cardinal::East => WorldPoint {
+                x: x + depth,
+                y: y + col,
+            },
+            Cardinal::West => WorldPoint {
+                x: x - depth,
+                y: y + col,
+            },
+        }
+    }
+}
+
+/// An exact fraction, so tiles exactly on the edge of a shadow are treated the
+/// same way from both directions.
+#[derive(Clone, Copy)]
+struct Slope {
+    num: i64,
+    den: i64,
+}
+
+impl Slope {
+    fn new(num: i64, den: i64) -> Self {
+        Slope { num, den }
+    }
+
+    fn of_tile(depth: i64, col: i64) -> Self {
+        Slope::new(2 * col - 1, 2 * depth)
+    }
+}
+
+struct Row {
+    depth: i64,
+    start_slope: Slope,
+    end_slope: Slope,
+}
+
+impl Row {
+    fn min_col(&self) -> i64 {
+        // round_ties_up(depth * start_slope)
+        let Slope { num, den } = self.start_slope;
+        (2 * self.depth * num + den).div_euclid(2 * den)
+    }
+
+    fn max_col(&self) -> i64 {
+        // round_ties_down(depth * end_slope)
+        let Slope { num, den } = self.end_slope;
+        -(den - 2 * self.depth * num).div_euclid(2 * den)
+    }
+
+    fn is_symmetric(&self, col: i64) -> bool {
+        col * self.start_slope.den >= self.depth * self.start_slope.num
+            && col * self.end_slope.den <= self.depth * self.end_slope.num
+    }
+
+    fn next(&self) -> Row {
+        Row {
+            depth: self.depth + 1,
+            start_slope: self.start_slope,
+            end_slope: self.end_slope,
+        }
+    }
+}
@@ -0,0 +1,721 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use rand::Rng;
+
+use crate::map_gen::{self, Tile};
+use crate::meta::{self, Command, GameRng, RunSummary};
+use crate::profiler::TurnProfiler;
+use crate::{component, entity, game_object, integrity, schema, system};
+
+/// A game in progress. Owns the game's database and the rng that everything
+/// random in it, including the SQL functions, comes from.
+#[derive(Debug)]
+pub struct Game {
+    pub db: rusqlite::Connection,
+    pub player: entity::Entity,
+    pub profiler: TurnProfiler,
+    pub is_creative: bool,
+    /// The save file the game is kept in or saved to. Replays have none, so
+    /// they never touch the disk.
+    pub path: Option<PathBuf>,
+    pub storage: Storage,
+    rng: Arc<Mutex<GameRng>>,
+    /// Why the last autosave failed, until someone takes it to report it
+    autosave_failure: Option<anyhow::Error>,
+}
+
+/// Where a game's database lives while it is played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Storage {
+    /// In memory, only written to the save file when the game is saved, so
+    /// turns never wait on the disk and quitting without saving is possible.
+    /// A crash loses everything since the last save but what the newest
+    /// backup holds, which is offered when the game is next loaded.
+    Memory,
+    /// In the save file, with every command committed to it as it is played
+    Disk,
+}
+
+/// Commands to play back, each with the turn it was originally given on
+pub type Recording = VecDeque<(i64, Command)>;
+
+pub enum StepResult {
+    /// The command couldn't be carried out, so no time passed
+    Ignored,
+    Continue,
+    Won,
+    Died(RunSummary),
+}
+
+impl Game {
+    /// Starts a game to be saved at `path`, replacing anything already
+    /// saved there. Nothing is written to a game kept in memory until it is
+    /// saved.
+    pub fn new<P: AsRef<Path>>(
+        path: P,
+        storage: Storage,
+        is_creative: bool,
+        seed: u64,
+        dungeon_generator: impl map_gen::Generator,
+    ) -> anyhow::Result<Game> {
+        let path = path.as_ref();
+        meta::remove_save(path)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Game::create(Some(path), storage, is_creative, seed, dungeon_generator)
+    }
+
+    /// Starts a game from its seed. A game without a save file is kept in
+    /// memory, whatever the storage asked for.
+    fn create(
+        path: Option<&Path>,
+        storage: Storage,
+        is_creative: bool,
+        seed: u64,
+        mut dungeon_generator: impl map_gen::Generator,
+    ) -> anyhow::Result<Game> {
+        // The seed is all it takes to reproduce a game
+        let rng = Arc::new(Mutex::new(meta::seeded_rng(seed)));
+        let db = match path {
+            Some(path) if storage == Storage::Disk => open_db(path, rng.clone())?,
+            _ => open_memory_db(rng.clone())?,
+        };
+
+        schema::migrate(&db)?;
+        db.execute_batch("BEGIN TRANSACTION")?;
+        component::metadata::set_seed(&db, seed)?;
+
+        let player = game_object::init_player(&db, is_creative)?;
+        let initial_dungeon = dungeon_generator.generate(
+            &mut rng.lock().unwrap(),
+            meta::WORLD_WIDTH,
+            meta::WORLD_HEIGHT,
+        );
+        game_object::init_level(&db, &initial_dungeon, player, 0, Tile::UpStairs)?;
+        system::update_fov(&db)?;
+        component::metadata::save_rng(&db, &rng.lock().unwrap())?;
+        component::metadata::update_save_info(&db)?;
+        db.execute_batch("COMMIT TRANSACTION")?;
+
+        let profiler = TurnProfiler::new();
+        Ok(Game {
+            db,
+            player,
+            profiler,
+            is_creative,
+            path: path.map(Path::to_path_buf),
+            storage,
+            rng,
+            autosave_failure: None,
+        })
+    }
+
+    /// Opens the game saved at `path`, along with a description of each
+    /// repair made to it. A damaged save is replaced by its newest backup
+    /// that isn't. A game kept in memory is copied out of the save, and only
+    /// repaired or migrated in there once saved again.
+    pub fn load<P: AsRef<Path>>(path: P, storage: Storage) -> anyhow::Result<(Game, Vec<String>)> {
+        let path = path.as_ref();
+        // Opening a missing save would create an empty one
+        if !path.exists() {
+            anyhow::bail!("there is no save at {}", path.display());
+        }
+        let rng = Arc::new(Mutex::new(meta::init_rng()));
+        let mut repairs = vec![];
+        let db = match open_save(path, storage, rng.clone()) {
+            Ok(db) => db,
+            Err(err) => {
+                let mut restored = None;
+                for n in 0..meta::BACKUP_COUNT {
+                    if !meta::backup_path(path, n).exists() {
+                        continue;
+                    }
+                    meta::restore_backup(path, n)?;
+                    if let Ok(db) = open_save(path, storage, rng.clone()) {
+                        restored = Some(db);
+                        break;
+                    }
+                }
+                let Some(db) = restored else {
+                    return Err(err);
+                };
+                repairs.push(format!(
+                    "restored its newest readable backup, as the save itself failed: {:#}",
+                    err
+                ));
+                db
+            }
+        };
+        schema::migrate(&db)?;
+        db.execute_batch("BEGIN TRANSACTION")?;
+        let repaired = integrity::repair(&db);
+        db.execute_batch(match repaired {
+            Ok(_) => "COMMIT TRANSACTION",
+            Err(_) => "ROLLBACK TRANSACTION",
+        })?;
+        repairs.extend(repaired?);
+        // Pick up the rng where the save left off, so reloading doesn't
+        // reroll anything
+        if let Some(saved_rng) = component::metadata::load_rng(&db)? {
+            *rng.lock().unwrap() = saved_rng;
+        }
+        let player = entity::load_player(&db)?;
+        let is_creative = component::player::is_creative(&db)?;
+        let profiler = TurnProfiler::new();
+        let game = Game {
+            db,
+            player,
+            profiler,
+            is_creative,
+            path: Some(path.to_path_buf()),
+            storage,
+            rng,
+            autosave_failure: None,
+        };
+        Ok((game, repairs))
+    }
+
+    /// Writes the game to its save file, if it has one. A game kept on disk
+    /// already is saved as of its last turn.
+    pub fn save(&self) -> rusqlite::Result<()> {
+        match (self.storage, &self.path) {
+            (Storage::Memory, Some(path)) => {
+                self.db.backup(rusqlite::DatabaseName::Main, path, None)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts a fresh copy of the saved game from its seed, and returns it
+    /// with the save's commands ready to be played back into it. The copy is
+    /// kept in memory with no save file of its own, and the save is only
+    /// read. Returns None if there is no save to replay.
+    pub fn replay<P: AsRef<Path>>(save_path: P) -> anyhow::Result<Option<(Game, Recording)>> {
+        if !save_path.as_ref().exists() {
+            return Ok(None);
+        }
+        let (seed, is_creative, commands) = {
+            let save = load_into_memory(save_path.as_ref(), Arc::new(Mutex::new(meta::init_rng())))
+                .context("couldn't open the save file")?;
+            integrity::check(&save)?;
+            schema::migrate(&save)?;
+            let Some(seed) = component::metadata::seed(&save)? else {
+                return Ok(None);
+            };
+            (
+                seed,
+                component::player::is_creative(&save)?,
+                component::replay::load(&save)?,
+            )
+        };
+        let game = if is_creative {
+            Game::create(None, Storage::Memory, true, seed, map_gen::EmptyGenerator)?
+        } else {
+            Game::create(
+                None,
+                Storage::Memory,
+                false,
+                seed,
+                map_gen::DefaultGenerator::new(),
+            )?
+        };
+        Ok(Some((game, commands.into())))
+    }
+
+    /// Carries out a command, then runs turns until the player has the
+    /// energy to act again. All of it is committed at once, so a step that
+    /// fails partway can be rolled back to before the command was given.
+    /// The game is backed up after any step that passes a multiple of
+    /// `AUTOSAVE_INTERVAL` turns.
+    pub fn step(&mut self, command: Command) -> anyhow::Result<StepResult> {
+        let turns_before = component::player::turns_passed(&self.db)?;
+        self.db.execute_batch("BEGIN TRANSACTION")?;
+        let result = self.play(command)?;
+        if let StepResult::Ignored = result {
+            self.rollback()?;
+            return Ok(result);
+        }
+        component::metadata::save_rng(&self.db, &self.rng.lock().unwrap())?;
+        component::metadata::update_save_info(&self.db)?;
+        self.db.execute_batch("COMMIT TRANSACTION")?;
+
+        // The step is already committed, so a failed backup is only reported
+        let turns_after = component::player::turns_passed(&self.db)?;
+        if turns_after / meta::AUTOSAVE_INTERVAL > turns_before / meta::AUTOSAVE_INTERVAL {
+            let start = std::time::Instant::now();
+            if let Err(err) = self.autosave() {
+                self.autosave_failure = Some(err);
+            }
+            self.profiler
+                .record(&self.db, turns_after, "autosave", start.elapsed())?;
+        }
+        Ok(result)
+    }
+
+    /// Abandons the step in progress, if one failed partway, leaving the
+    /// game as it was before its command was given
+    pub fn rollback(&self) -> rusqlite::Result<()> {
+        if !self.db.is_autocommit() {
+            self.db.execute_batch("ROLLBACK TRANSACTION")?;
+        }
+        // The rng goes back too, so retrying plays out like the first try
+        if let Some(saved_rng) = component::metadata::load_rng(&self.db)? {
+            *self.rng.lock().unwrap() = saved_rng;
+        }
+        Ok(())
+    }
+
+    /// Why the last autosave failed, if it did and hasn't been reported yet.
+    /// The turns played since are still in the game, just not backed up.
+    pub fn take_autosave_failure(&mut self) -> Option<anyhow::Error> {
+        self.autosave_failure.take()
+    }
+
+    /// Whether the player has reached the last floor. That floor is never
+    /// built, so a won game can't be played on.
+    pub fn has_won(&self) -> rusqlite::Result<bool> {
+        Ok(component::player::depth(&self.db)? >= meta::WIN_DEPTH)
+    }
+
+    pub fn is_dead(&self) -> rusqlite::Result<bool> {
+        component::player::is_dead(&self.db)
+    }
+
+    pub fn summary(&self) -> rusqlite::Result<RunSummary> {
+        RunSummary::load(&self.db)
+    }
+
+    /// Everything a step does, inside the step's transaction
+    fn play(&mut self, command: Command) -> anyhow::Result<StepResult> {
+        if !self.perform(command)? {
+            return Ok(StepResult::Ignored);
+        }
+        if self.has_won()? {
+            return Ok(StepResult::Won);
+        }
+        self.act_player(command.cost())?;
+        while !component::energy::is_ready(&self.db, self.player)? {
+            if self.run_turn()? {
+                return Ok(StepResult::Died(self.summary()?));
+            }
+        }
+        Ok(StepResult::Continue)
+    }
+
+    /// Carries out a command and records it in the replay log. Returns
+    /// false, without recording anything, if the command couldn't be done.
+    fn perform(&self, command: Command) -> rusqlite::Result<bool> {
+        let db = &self.db;
+        let player = self.player;
+        let turn = component::player::turns_passed(db)?;
+        let accepted = match command {
+            Command::Move { dx, dy } => {
+                component::velocity::set(db, player, dx, dy)?;
+                true
+            }
+            Command::Wait => true,
+            Command::OpenDoor | Command::CloseDoor => {
+                let currently_open = matches!(command, Command::CloseDoor);
+                component::door::toggle_adjacent(db, player, currently_open)? > 0
+            }
+            Command::FollowTransition => {
+                let previous_level = component::player::level(db)?;
+                let followed = system::follow_transition(db)?.is_some();
+                // The game is over once the player reaches the last floor,
+                // so there's no need to build it
+                if followed && component::player::depth(db)? < meta::WIN_DEPTH {
+                    game_object::change_level(db, &self.rng, player, &previous_level)?;
+                }
+                followed
+            }
+            Command::PickUp => component::inventory::pick_up(db, player)? > 0,
+            Command::Drop(item) => component::inventory::drop(db, player, item)? > 0,
+            Command::Use(item) => {
+                component::effect::apply(db, player, item)?
+                    || component::equipment::toggle(db, player, item)?
+            }
+        };
+        if !accepted {
+            return Ok(false);
+        }
+        if !matches!(command, Command::Move { .. }) {
+            component::velocity::set(db, player, 0, 0)?;
+        }
+        component::replay::record(db, turn, command)?;
+        Ok(true)
+    }
+
+    /// Moves the player, or has them attack or open a door, as the command
+    /// they were just given set up, and charges them for it.
+    fn act_player(&self, cost: i64) -> rusqlite::Result<()> {
+        let db = &self.db;
+        db.execute_batch("SAVEPOINT act_player")?;
+        component::energy::set_acting(db, self.player)?;
+        system::operate_doors(db)?;
+        system::move_actors(db)?;
+        // Nothing the player just finished off gets to hit back
+        system::cull_dead(db)?;
+        component::energy::spend(db, self.player, cost)?;
+        component::energy::clear_acting(db)?;
+        db.execute_batch("RELEASE act_player")
+    }
+
+    /// Copies the game over the newest backup of its save, after moving the
+    /// older backups down. A crash while copying only spoils the newest one.
+    /// Games kept on disk have their write-ahead log moved into the save
+    /// first. Games without a save file aren't backed up.
+    pub fn autosave(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if self.storage == Storage::Disk {
+            self.db
+                .query_row("PRAGMA wal_checkpoint(TRUNCATE)", (), |_| Ok(()))?;
+        }
+        for n in (1..meta::BACKUP_COUNT).rev() {
+            match std::fs::rename(meta::backup_path(path, n - 1), meta::backup_path(path, n)) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        self.db.backup(
+            rusqlite::DatabaseName::Main,
+            meta::backup_path(path, 0),
+            None,
+        )?;
+        Ok(())
+    }
+
+    /// Runs every system for one turn. Returns whether the player died.
+    fn run_turn(&mut self) -> anyhow::Result<bool> {
+        let db = &self.db;
+        db.execute_batch("SAVEPOINT run_turn")?;
+        let mut turn = self.profiler.start();
+        component::energy::gain(db)?;
+        // The most energetic go first, and anything quick enough to act
+        // more than once a turn keeps going until it runs out of energy
+        while component::energy::select_next_acting(db)? > 0 {
+            system::apply_ai(db)?;
+            system::operate_doors(db)?;
+            system::move_actors(db)?;
+            component::energy::finish_acting(db)?;
+        }
+        turn.split("actors");
+        component::player::pass_time(db, 1)?;
+        turn.split("time");
+        system::apply_regen(db)?;
+        turn.split("regen");
+        for _ in 0..25 {
+            game_object::generate_particles(db, 25)?;
+        }
+        turn.split("particles");
+        for _ in 0..4 {
+            game_object::generate_enemies(db, 10)?;
+        }
+        game_object::generate_chasers(db, 10)?;
+        turn.split("enemies");
+        system::cull_dead(db)?;
+        system::cull_ephemeral(db)?;
+        turn.split("culling");
+        system::update_fov(db)?;
+        turn.split("fov");
+        let turn_num = component::player::turns_passed(db)?;
+
+        let actor_count = component::actor::count(db)?;
+        let is_dead = component::player::is_dead(db)?;
+        self.profiler.end(db, turn_num, turn, actor_count)?;
+        db.execute_batch("RELEASE run_turn")?;
+        Ok(is_dead)
+    }
+}
+
+fn add_pcg_randint_function(
+    db: &rusqlite::Connection,
+    rng: Arc<Mutex<GameRng>>,
+) -> rusqlite::Result<()> {
+    use rusqlite::functions::FunctionFlags;
+    let randint_rng = rng.clone();
+    db.create_scalar_function(
+        "pcg_randint",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY,
+        move |ctx| {
+            let min = ctx.get::<i32>(0)?;
+            let max = ctx.get::<i32>(1)?;
+            let spread = (max - min).abs();
+            let start = min.min(max);
+            let num = randint_rng
+                .lock()
+                .unwrap()
+                .gen_range(start..=start + spread);
+            Ok(num)
+        },
+    )?;
+    db.create_scalar_function(
+        "pcg_random",
+        0,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY,
+        move |_ctx| {
+            let num = rng.lock().unwrap().gen::<i64>();
+            Ok(num)
+        },
+    )
+}
+
+/// Sets up what the systems' SQL relies on
+fn init_db(db: &rusqlite::Connection, rng: Arc<Mutex<GameRng>>) -> rusqlite::Result<()> {
+    rusqlite::vtab::series::load_module(db)?;
+    add_pcg_randint_function(db, rng)?;
+
+    db.execute_batch("PRAGMA foreign_keys = TRUE")
+}
+
+fn open_memory_db(rng: Arc<Mutex<GameRng>>) -> rusqlite::Result<rusqlite::Connection> {
+    let db = rusqlite::Connection::open_in_memory()?;
+    init_db(&db, rng)?;
+    Ok(db)
+}
+
+/// Opens a save to be played, failing if it is damaged
+fn open_save(
+    path: &Path,
+    storage: Storage,
+    rng: Arc<Mutex<GameRng>>,
+) -> anyhow::Result<rusqlite::Connection> {
+    let db = match storage {
+        Storage::Memory => load_into_memory(path, rng),
+        Storage::Disk => open_db(path, rng),
+    }
+    .context("couldn't open the save file")?;
+    integrity::check(&db)?;
+    Ok(db)
+}
+
+/// Copies the save at `path` into a new in-memory database
+fn load_into_memory(
+    path: &Path,
+    rng: Arc<Mutex<GameRng>>,
+) -> rusqlite::Result<rusqlite::Connection> {
+    let save =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    // Reading the header first explains a file that isn't a save better
+    // than the copy failing would
+    save.query_row("PRAGMA user_version", (), |_| Ok(()))?;
+    let mut db = open_memory_db(rng)?;
+    rusqlite::backup::Backup::new(&save, &mut db)?.run_to_completion(
+        i32::MAX,
+        std::time::Duration::ZERO,
+        None,
+    )?;
+    Ok(db)
+}
+
+pub(crate) fn open_db<P: AsRef<Path>>(
+    path: P,
+    rng: Arc<Mutex<GameRng>>,
+) -> rusqlite::Result<rusqlite::Connection> {
+    let db = rusqlite::Connection::open(path)?;
+    init_db(&db, rng)?;
+    // Commands are committed to a write-ahead log, so a crash partway
+    // through one leaves the save as the last command did. NORMAL only syncs the log at
+    // checkpoints: a power cut can lose the last few turns, but a crash of
+    // the game itself loses nothing committed.
+    db.query_row("PRAGMA journal_mode = WAL", (), |_| Ok(()))?;
+    db.pragma_update(None, "synchronous", "NORMAL")?;
+
+    Ok(db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory of the test's own to save into
+    fn save_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rust-roguelike-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn new_game(path: &Path, storage: Storage) -> Game {
+        Game::new(path, storage, true, 7, map_gen::EmptyGenerator).unwrap()
+    }
+
+    /// The save file and everything kept next to it
+    fn save_files(path: &Path) -> Vec<PathBuf> {
+        let mut files = vec![path.to_path_buf()];
+        for suffix in ["-wal", "-shm"] {
+            let mut file = path.as_os_str().to_owned();
+            file.push(suffix);
+            files.push(file.into());
+        }
+        files.extend((0..meta::BACKUP_COUNT).map(|n| meta::backup_path(path, n)));
+        files
+    }
+
+    #[test]
+    fn saving_a_game_in_memory_keeps_its_turn_and_rng() {
+        let dir = save_dir("saving_a_game_in_memory");
+        let path = dir.join("game.db");
+        let mut game = new_game(&path, Storage::Memory);
+        assert!(!path.exists());
+        for _ in 0..3 {
+            game.step(Command::Wait).unwrap();
+        }
+        game.save().unwrap();
+
+        let (loaded, repairs) = Game::load(&path, Storage::Memory).unwrap();
+        assert!(repairs.is_empty());
+        assert_eq!(
+            component::player::turns_passed(&loaded.db).unwrap(),
+            component::player::turns_passed(&game.db).unwrap()
+        );
+        let next = |game: &Game| game.rng.lock().unwrap().gen::<u64>();
+        assert_eq!(next(&loaded), next(&game));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_step_is_undone_entirely() {
+        let dir = save_dir("a_failed_step");
+        let mut game = new_game(&dir.join("game.db"), Storage::Memory);
+        game.step(Command::Wait).unwrap();
+        let state = |game: &Game| -> (i64, i64, i64, String) {
+            game.db
+                .query_row(
+                    "SELECT
+                        Player.turn,
+                        (SELECT COUNT(*) FROM Replay),
+                        Energy.energy
+                    FROM Player
+                    JOIN Energy ON Energy.entity = Player.entity",
+                    (),
+                    |row| {
+                        Ok((
+                            row.get(0)?,
+                            row.get(1)?,
+                            row.get(2)?,
+                            serde_json::to_string(&*game.rng.lock().unwrap()).unwrap(),
+                        ))
+                    },
+                )
+                .unwrap()
+        };
+        let before = state(&game);
+
+        // Fails once the player has acted and a turn is underway
+        game.db
+            .execute_batch(
+                "CREATE TEMP TRIGGER fail_turn AFTER UPDATE OF turn ON Player
+                BEGIN SELECT RAISE(FAIL, 'the turn failed'); END",
+            )
+            .unwrap();
+        assert!(game.step(Command::Wait).is_err());
+        game.rollback().unwrap();
+        assert_eq!(state(&game), before);
+
+        game.db.execute_batch("DROP TRIGGER fail_turn").unwrap();
+        game.step(Command::Wait).unwrap();
+        assert_eq!(state(&game).0, before.0 + 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_damaged_save_is_replaced_by_its_backup() {
+        let dir = save_dir("a_damaged_save");
+        let path = dir.join("game.db");
+        let mut game = new_game(&path, Storage::Memory);
+        game.step(Command::Wait).unwrap();
+        game.autosave().unwrap();
+        let backed_up = component::player::turns_passed(&game.db).unwrap();
+        game.step(Command::Wait).unwrap();
+        game.save().unwrap();
+        std::fs::write(&path, "not a save").unwrap();
+
+        let (loaded, repairs) = Game::load(&path, Storage::Memory).unwrap();
+        assert_eq!(repairs.len(), 1, "{:?}", repairs);
+        assert_eq!(
+            component::player::turns_passed(&loaded.db).unwrap(),
+            backed_up
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_damaged_save_without_backups_is_refused() {
+        let dir = save_dir("a_damaged_save_without_backups");
+        let path = dir.join("game.db");
+        std::fs::write(&path, "not a save").unwrap();
+        assert!(Game::load(&path, Storage::Memory).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn backups_made_since_the_last_save_can_be_restored_or_discarded() {
+        let dir = save_dir("backups_made_since");
+        let path = dir.join("game.db");
+        let mut game = new_game(&path, Storage::Memory);
+        game.save().unwrap();
+        game.step(Command::Wait).unwrap();
+        game.autosave().unwrap();
+        meta::restore_backup(&path, 0).unwrap();
+        let (loaded, _) = Game::load(&path, Storage::Memory).unwrap();
+        assert_eq!(component::player::turns_passed(&loaded.db).unwrap(), 1);
+
+        game.step(Command::Wait).unwrap();
+        game.autosave().unwrap();
+        meta::discard_unsaved_backups(&path).unwrap();
+        assert!(!meta::backup_path(&path, 0).exists());
+        assert!(meta::backup_path(&path, 1).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn backups_are_rotated() {
+        let dir = save_dir("backups_are_rotated");
+        let path = dir.join("game.db");
+        let game = new_game(&path, Storage::Memory);
+        for _ in 0..meta::BACKUP_COUNT + 1 {
+            game.autosave().unwrap();
+        }
+        let backups = std::fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .path()
+                    .to_string_lossy()
+                    .ends_with(meta::BACKUP_EXTENSION)
+            })
+            .count();
+        assert_eq!(backups, meta::BACKUP_COUNT);
+        assert!(meta::backup_path(&path, meta::BACKUP_COUNT - 1).exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn removing_a_save_removes_its_log_and_backups() {
+        let dir = save_dir("removing_a_save");
+        let path = dir.join("game.db");
+        let game = new_game(&path, Storage::Disk);
+        for _ in 0..meta::BACKUP_COUNT {
+            game.autosave().unwrap();
+        }
+        for file in save_files(&path) {
+            assert!(file.exists(), "{} wasn't made", file.display());
+        }
+        meta::remove_save(&path).unwrap();
+        for file in save_files(&path) {
+            assert!(!file.exists(), "{} was left behind", file.display());
+        }
+        drop(game);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
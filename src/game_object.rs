@@ -1,9 +1,9 @@
-use crate::{component, entity};
+use crate::map_gen::{self, Generator, Tile};
+use crate::{component, entity, meta, system};
 use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use std::sync::Mutex;
 
-pub const WIN_LEVEL: &str = "win";
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub struct WorldPoint {
     pub x: i64,
     pub y: i64,
@@ -23,25 +23,28 @@ impl Color {
     }
 }
 
-impl From<Color> for ggez::graphics::Color {
-    fn from(value: Color) -> Self {
-        ggez::graphics::Color {
-            r: value.r as f32 / 255.0,
-            g: value.r as f32 / 255.0,
-            b: value.r as f32 / 255.0,
-            a: 1.0,
-        }
-    }
-}
-
 pub const GROUND_COLOR: Color = Color::from_u8s((80, 80, 80));
 pub const PARTICLE_COLOR: Color = Color::from_u8s((200, 200, 200));
 pub const ENEMY_COLOR: Color = Color::from_u8s((255, 255, 255));
 pub const PLAYER_COLOR: Color = Color::from_u8s((255, 255, 255));
 pub const WALL_COLOR: Color = Color::from_u8s((255, 255, 255));
+pub const DOOR_COLOR: Color = Color::from_u8s((180, 120, 60));
+pub const GOLD_COLOR: Color = Color::from_u8s((255, 215, 0));
+pub const POTION_COLOR: Color = Color::from_u8s((220, 40, 40));
+pub const SCROLL_COLOR: Color = Color::from_u8s((230, 230, 200));
+pub const EQUIPMENT_COLOR: Color = Color::from_u8s((150, 200, 255));
 pub const STAIR_COLOR: Color = Color::from_u8s((255, 255, 255));
+pub const REMEMBERED_COLOR: Color = Color::from_u8s((60, 60, 60));
 pub const BACKGROUND_COLOR: Color = Color::from_u8s((0, 0, 0));
 
+pub const PLAYER_NAME: &str = "Adventurer";
+pub const ENEMY_NAME: &str = "wandering monster";
+
+pub const PLAYER_HEALTH: i64 = 30;
+pub const PLAYER_ATTACK: i64 = 6;
+pub const PLAYER_DEFENSE: i64 = 1;
+pub const ENEMY_ATTACK: i64 = 3;
+
 #[derive(Debug)]
 pub struct MenuColor {
     pub fg: Color,
@@ -70,7 +73,7 @@ pub enum Plane {
 }
 
 impl ToSql for Plane {
-    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
         Ok((*self as i64).into())
     }
 }
@@ -87,12 +90,46 @@ pub fn init_player(
 ) -> rusqlite::Result<entity::Entity> {
     let player = entity::create(db)?;
     component::player::set(db, player, is_creative)?;
+    component::name::set(db, player, PLAYER_NAME)?;
     component::velocity::set(db, player, 0, 0)?;
+    // Ready to act straight away
+    component::energy::set(
+        db,
+        player,
+        component::energy::NORMAL_SPEED,
+        component::energy::ACTION_COST,
+    )?;
     component::collision::set(db, player, false, true, false)?;
+    component::health::set(db, player, PLAYER_HEALTH, PLAYER_HEALTH, 0)?;
+    component::attack::set(db, player, PLAYER_ATTACK)?;
+    component::defense::set(db, player, PLAYER_DEFENSE)?;
     Ok(player)
 }
 
-pub fn init_floor(db: &rusqlite::Connection, pos: WorldPoint) -> rusqlite::Result<entity::Entity> {
+pub fn init_player_actor(
+    db: &rusqlite::Connection,
+    player: entity::Entity,
+    pos: WorldPoint,
+    level: &str,
+) -> rusqlite::Result<()> {
+    component::actor::set(
+        db,
+        component::actor::Actor {
+            entity: player,
+            tile: "@".into(),
+            pos,
+            color: PLAYER_COLOR,
+            plane: Plane::Player,
+            level: level.into(),
+        },
+    )
+}
+
+pub fn init_floor(
+    db: &rusqlite::Connection,
+    pos: WorldPoint,
+    level: &str,
+) -> rusqlite::Result<entity::Entity> {
     let panel = entity::create(db)?;
     component::actor::set(
         db,
@@ -102,6 +139,7 @@ pub fn init_floor(db: &rusqlite::Connection, pos: WorldPoint) -> rusqlite::Resul
             pos,
             color: GROUND_COLOR,
             plane: Plane::Ground,
+            level: level.into(),
         },
     )?;
     component::collision::set(db, panel, true, false, false)?;
@@ -112,6 +150,7 @@ pub fn init_wall(
     db: &rusqlite::Connection,
     tile: &str,
     pos: WorldPoint,
+    level: &str,
 ) -> rusqlite::Result<entity::Entity> {
     let panel = entity::create(db)?;
     component::actor::set(
@@ -122,39 +161,250 @@ pub fn init_wall(
             pos,
             color: WALL_COLOR,
             plane: Plane::Wall,
+            level: level.into(),
         },
     )?;
     component::collision::set(db, panel, true, true, false)?;
     Ok(panel)
 }
 
+pub fn init_door(
+    db: &rusqlite::Connection,
+    pos: WorldPoint,
+    level: &str,
+    open: bool,
+) -> rusqlite::Result<entity::Entity> {
+    let door = entity::create(db)?;
+    let tile = if open {
+        component::door::OPEN_TILE
+    } else {
+        component::door::CLOSED_TILE
+    };
+    component::actor::set(
+        db,
+        component::actor::Actor {
+            entity: door,
+            tile: tile.into(),
+            pos,
+            color: DOOR_COLOR,
+            plane: Plane::Objects,
+            level: level.into(),
+        },
+    )?;
+    component::collision::set(db, door, false, !open, false)?;
+    component::door::set(db, door, open)?;
+    Ok(door)
+}
+
+pub fn init_stairs(
+    db: &rusqlite::Connection,
+    tile: &str,
+    pos: WorldPoint,
+    level: &str,
+    destination: &str,
+) -> rusqlite::Result<entity::Entity> {
+    let stairs = entity::create(db)?;
+    component::actor::set(
+        db,
+        component::actor::Actor {
+            entity: stairs,
+            tile: tile.into(),
+            pos,
+            color: STAIR_COLOR,
+            plane: Plane::Objects,
+            level: level.into(),
+        },
+    )?;
+    component::transition::set(db, stairs, destination)?;
+    Ok(stairs)
+}
+
+/// Creates the entities for every tile of `dungeon`, which becomes the floor
+/// at `depth`. The player is placed on the first `arrival` tile, so they
+/// arrive on the staircase matching the one they left by.
+pub fn init_level(
+    db: &rusqlite::Connection,
+    dungeon: &map_gen::Dungeon,
+    player: entity::Entity,
+    depth: i64,
+    arrival: Tile,
+) -> rusqlite::Result<()> {
+    let level = depth.to_string();
+    let mut player_placed = false;
+    for (tile, x, y) in dungeon.iter() {
+        let pos = WorldPoint { x, y };
+
+        if tile == Tile::Unused {
+            continue;
+        } else if tile == Tile::Floor || tile == Tile::Corridor {
+            init_floor(db, pos, &level)?;
+        } else if tile == Tile::Wall {
+            init_wall(db, "#", pos, &level)?;
+        } else if tile == Tile::ClosedDoor || tile == Tile::OpenDoor {
+            init_floor(db, pos, &level)?;
+            init_door(db, pos, &level, tile == Tile::OpenDoor)?;
+        } else if tile == Tile::DownStairs {
+            init_floor(db, pos, &level)?;
+            init_stairs(db, ">", pos, &level, &(depth + 1).to_string())?;
+        } else if tile == Tile::UpStairs {
+            init_floor(db, pos, &level)?;
+            // There is nothing above the first floor
+            if depth > 0 {
+                init_stairs(db, "<", pos, &level, &(depth - 1).to_string())?;
+            }
+        }
+
+        if tile == arrival && !player_placed {
+            init_player_actor(db, player, pos, &level)?;
+            player_placed = true;
+        }
+    }
+    // Not every map has the tile the player arrives by
+    if !player_placed {
+        component::actor::set_on_random_empty_ground(db, player, "@", PLAYER_COLOR, Plane::Player)?;
+    }
+    for _ in 0..meta::GOLD_PER_LEVEL {
+        generate_item(db, "gold coins", "$", GOLD_COLOR)?;
+    }
+    for _ in 0..meta::HEALING_POTIONS_PER_LEVEL {
+        let potion = generate_item(db, "potion of healing", "!", POTION_COLOR)?;
+        component::effect::add(db, potion, component::effect::EFFECT_HEAL, 10, 0)?;
+    }
+    for _ in 0..meta::TELEPORT_SCROLLS_PER_LEVEL {
+        let scroll = generate_item(db, "scroll of teleportation", "?", SCROLL_COLOR)?;
+        component::effect::add(db, scroll, component::effect::EFFECT_TELEPORT, 0, 0)?;
+    }
+    for _ in 0..meta::FIREBALL_SCROLLS_PER_LEVEL {
+        let scroll = generate_item(db, "scroll of fireball", "?", SCROLL_COLOR)?;
+        component::effect::add(db, scroll, component::effect::EFFECT_AREA_DAMAGE, 8, 3)?;
+    }
+    // One piece of equipment per floor, cycling through the slots
+    let (name, tile, slot, bonus) = match depth % 3 {
+        0 => (
+            "dagger",
+            "(",
+            component::equipment::SLOT_WEAPON,
+            component::equipment::StatBonus {
+                attack: 3,
+                ..Default::default()
+            },
+        ),
+        1 => (
+            "leather armour",
+            "[",
+            component::equipment::SLOT_ARMOUR,
+            component::equipment::StatBonus {
+                defense: 2,
+                max_health: 5,
+                ..Default::default()
+            },
+        ),
+        _ => (
+            "ring of regeneration",
+            "=",
+            component::equipment::SLOT_RING,
+            component::equipment::StatBonus {
+                regen: 1,
+                ..Default::default()
+            },
+        ),
+    };
+    let equipment = generate_item(db, name, tile, EQUIPMENT_COLOR)?;
+    component::equipment::set(db, equipment, slot, bonus)?;
+    Ok(())
+}
+
+/// Moves the player to the level they have just transitioned to, generating
+/// it if this is the first visit. Levels that have already been visited are
+/// kept exactly as the player left them.
+pub fn change_level(
+    db: &rusqlite::Connection,
+    rng: &Mutex<meta::GameRng>,
+    player: entity::Entity,
+    previous_level: &str,
+) -> rusqlite::Result<()> {
+    let level = component::player::level(db)?;
+    db.execute_batch("SAVEPOINT change_level")?;
+    if let Some(pos) = component::actor::find_transition(db, &level, previous_level)? {
+        init_player_actor(db, player, pos, &level)?;
+    } else {
+        let depth = component::player::depth(db)?;
+        let arrival = if depth > previous_level.parse().unwrap_or(0) {
+            Tile::UpStairs
+        } else {
+            Tile::DownStairs
+        };
+        let dungeon = map_gen::DefaultGenerator::new().generate(
+            &mut rng.lock().unwrap(),
+            meta::WORLD_WIDTH,
+            meta::WORLD_HEIGHT,
+        );
+        init_level(db, &dungeon, player, depth, arrival)?;
+    }
+    system::update_fov(db)?;
+    db.execute_batch("RELEASE change_level")?;
+    Ok(())
+}
+
+pub fn generate_item(
+    db: &rusqlite::Connection,
+    name: &str,
+    tile: &str,
+    color: Color,
+) -> rusqlite::Result<entity::Entity> {
+    let item = entity::create(db)?;
+    component::actor::set_on_random_empty_ground(db, item, tile, color, Plane::Objects)?;
+    component::item::set(db, item, tile, color)?;
+    component::name::set(db, item, name)?;
+    Ok(item)
+}
+
 pub fn generate_particles(db: &rusqlite::Connection, lifespan: i64) -> rusqlite::Result<()> {
     let entity = entity::create(db)?;
     component::actor::set_on_random_empty_ground(
         db,
         entity,
         "*",
-        PARTICLE_COLOR.into(),
+        PARTICLE_COLOR,
         Plane::Particles,
     )?;
     component::velocity::set_random(db, entity, -1..=1)?;
+    component::energy::set(db, entity, component::energy::NORMAL_SPEED, 0)?;
     component::health::set(db, entity, lifespan, lifespan, -1)?;
     component::collision::set(db, entity, false, false, true)?;
     Ok(())
 }
 
 pub fn generate_enemies(db: &rusqlite::Connection, lifespan: i64) -> rusqlite::Result<()> {
-    let entity = entity::create(db).unwrap();
-    component::actor::set_on_random_empty_ground(
+    let entity = generate_monster(db, lifespan)?;
+    component::ai::set_random(db, entity)?;
+    // Wanderers range from sluggish to twice as quick as the player
+    component::energy::set_random_speed(
         db,
         entity,
-        "x",
-        ENEMY_COLOR.into(),
-        Plane::Enemies,
+        &[
+            component::energy::SLOW_SPEED,
+            component::energy::NORMAL_SPEED,
+            component::energy::FAST_SPEED,
+        ],
     )?;
+    Ok(())
+}
+
+pub fn generate_chasers(db: &rusqlite::Connection, lifespan: i64) -> rusqlite::Result<()> {
+    let entity = generate_monster(db, lifespan)?;
+    component::ai::set_chase(db, entity)?;
+    component::energy::set(db, entity, component::energy::NORMAL_SPEED, 0)?;
+    Ok(())
+}
+
+fn generate_monster(db: &rusqlite::Connection, lifespan: i64) -> rusqlite::Result<entity::Entity> {
+    let entity = entity::create(db)?;
+    component::actor::set_on_random_empty_ground(db, entity, "x", ENEMY_COLOR, Plane::Enemies)?;
     component::velocity::set(db, entity, 0, 0)?;
     component::health::set(db, entity, lifespan, lifespan, -1)?;
     component::collision::set(db, entity, false, true, false)?;
-    component::ai::set_random(db, entity)?;
-    Ok(())
+    component::attack::set(db, entity, ENEMY_ATTACK)?;
+    component::name::set(db, entity, ENEMY_NAME)?;
+    Ok(entity)
 }
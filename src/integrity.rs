@@ -0,0 +1,209 @@
+//! Checks run on a save before it is played, so a damaged file fails with a
+//! readable error instead of somewhere deep inside a turn.
+
+use anyhow::Context;
+
+use crate::game_object::Plane;
+
+/// Fails if SQLite itself finds the file damaged. Run this before anything
+/// else reads the save.
+pub fn check(db: &rusqlite::Connection) -> anyhow::Result<()> {
+    let problems = db
+        .prepare("PRAGMA integrity_check")
+        .and_then(|mut query| {
+            query
+                .query_map((), |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .context("the save file is damaged or isn't a save at all")?;
+    if problems != ["ok"] {
+        anyhow::bail!("the save file is damaged: {}", problems.join("; "));
+    }
+    Ok(())
+}
+
+/// Fixes what can be fixed in an up to date save, and describes each fix.
+/// Fails if the save has no single player to play as.
+pub fn repair(db: &rusqlite::Connection) -> anyhow::Result<Vec<String>> {
+    let mut repairs = vec![];
+
+    let orphans = remove_orphans(db)?;
+    if orphans > 0 {
+        repairs.push(format!(
+            "removed {} components of missing entities",
+            orphans
+        ));
+    }
+
+    let players: i64 = db.query_row("SELECT COUNT(*) FROM Player", (), |row| row.get(0))?;
+    if players != 1 {
+        anyhow::bail!("the save should have one player, but has {}", players);
+    }
+    let has_actor: bool = db.query_row(
+        "SELECT COUNT(*) > 0 FROM Actor JOIN Player ON Player.entity = Actor.entity",
+        (),
+        |row| row.get(0),
+    )?;
+    if !has_actor {
+        anyhow::bail!("the player isn't anywhere in the dungeon");
+    }
+
+    let collisions = add_missing_collision(db)?;
+    if collisions > 0 {
+        repairs.push(format!("gave {} actors back their collision", collisions));
+    }
+
+    let duplicates = remove_duplicates(db)?;
+    if duplicates > 0 {
+        repairs.push(format!("removed {} actors stacked on another", duplicates));
+    }
+    Ok(repairs)
+}
+
+/// Deletes component rows whose entity no longer exists
+fn remove_orphans(db: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    let orphans = db
+        .prepare("PRAGMA foreign_key_check")?
+        .query_map((), |row| {
+            Ok((row.get::<_, String>("table")?, row.get::<_, i64>("rowid")?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (table, rowid) in &orphans {
+        db.execute(&format!("DELETE FROM {} WHERE rowid = ?", table), [rowid])?;
+    }
+    Ok(orphans.len())
+}
+
+/// Rebuilds the collision every plane but objects has, from the plane the
+/// actor is drawn on
+fn add_missing_collision(db: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    db.execute(
+        "INSERT INTO Collision (entity, ground, solid, ephemeral)
+        SELECT
+            Actor.entity,
+            Actor.plane IN (:ground, :wall),
+            Actor.plane IN (:player, :enemies, :wall),
+            Actor.plane = :particles
+        FROM Actor
+        LEFT JOIN Collision ON Collision.entity = Actor.entity
+        WHERE Collision.entity IS NULL AND Actor.plane != :objects",
+        rusqlite::named_params! {
+            ":ground": Plane::Ground,
+            ":player": Plane::Player,
+            ":enemies": Plane::Enemies,
+            ":wall": Plane::Wall,
+            ":particles": Plane::Particles,
+            ":objects": Plane::Objects,
+        },
+    )
+}
+
+/// Only one solid and one ground actor can share a tile, whatever planes
+/// they are drawn on. The player is kept over anything else, then the oldest
+/// entity.
+fn remove_duplicates(db: &rusqlite::Connection) -> rusqlite::Result<usize> {
+    db.execute(
+        "DELETE FROM Entity
+        WHERE id IN (
+            SELECT entity FROM (
+                SELECT
+                    Actor.entity,
+                    ROW_NUMBER() OVER (
+                        PARTITION BY
+                            Actor.level, Actor.x, Actor.y,
+                            Collision.solid, Collision.ground
+                        ORDER BY Actor.entity IN (SELECT entity FROM Player) DESC, Actor.entity
+                    ) AS rank
+                FROM Actor
+                JOIN Collision ON Collision.entity = Actor.entity
+                WHERE Collision.solid = 1 OR Collision.ground = 1
+            )
+            WHERE rank > 1
+        )",
+        (),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity;
+    use crate::test_fixtures::{init_player, new_db, spawn};
+
+    fn exists(db: &rusqlite::Connection, entity: entity::Entity) -> bool {
+        db.query_row(
+            "SELECT COUNT(*) > 0 FROM Entity WHERE id = ?",
+            [entity],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn orphaned_components_are_removed() {
+        let db = new_db();
+        init_player(&db, 1, 1);
+        db.execute_batch(
+            "
+            PRAGMA foreign_keys = FALSE;
+            INSERT INTO Health (entity, max, current, regen) VALUES (999, 10, 10, 0);
+            PRAGMA foreign_keys = TRUE;
+            ",
+        )
+        .unwrap();
+        assert_eq!(repair(&db).unwrap().len(), 1);
+        let orphans: i64 = db
+            .query_row(
+                "SELECT COUNT(*) FROM Health WHERE entity = 999",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(orphans, 0);
+        assert!(repair(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_collision_is_rebuilt() {
+        let db = new_db();
+        init_player(&db, 1, 1);
+        let enemy = spawn(&db, 5, 5, true);
+        db.execute("DELETE FROM Collision WHERE entity = ?", [enemy])
+            .unwrap();
+        assert_eq!(repair(&db).unwrap().len(), 1);
+        let (ground, solid, ephemeral): (bool, bool, bool) = db
+            .query_row(
+                "SELECT ground, solid, ephemeral FROM Collision WHERE entity = ?",
+                [enemy],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!((ground, solid, ephemeral), (false, true, false));
+    }
+
+    #[test]
+    fn stacked_solids_keep_the_player_then_the_oldest() {
+        let db = new_db();
+        let player = init_player(&db, 1, 1);
+        let on_player = spawn(&db, 1, 1, true);
+        let oldest = spawn(&db, 5, 5, true);
+        let newest = spawn(&db, 5, 5, true);
+        assert_eq!(repair(&db).unwrap().len(), 1);
+        assert!(exists(&db, player));
+        assert!(!exists(&db, on_player));
+        assert!(exists(&db, oldest));
+        assert!(!exists(&db, newest));
+    }
+
+    #[test]
+    fn saves_without_exactly_one_player_are_refused() {
+        let db = new_db();
+        let err = repair(&db).unwrap_err().to_string();
+        assert!(err.contains("has 0"), "{}", err);
+
+        init_player(&db, 1, 1);
+        init_player(&db, 5, 5);
+        let err = repair(&db).unwrap_err().to_string();
+        assert!(err.contains("has 2"), "{}", err);
+    }
+}
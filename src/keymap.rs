@@ -0,0 +1,551 @@
+//! Which keys do what, in game and in menus. The defaults cover the arrow
+//! keys, the numpad and vi-keys, and any of it can be remapped on the options
+//! screen or in `keymap.toml`, which lists the keys for each action by name:
+//!
+//! ```toml
+//! move_west = ["Left", "H", "Numpad4"]
+//! wait = ["Period", "Numpad5"]
+//! ```
+//!
+//! Actions left out of the file keep their default keys, unless the file
+//! gives one of them to another action. A file that gives the same key to
+//! two actions in game, or two in menus, is refused.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::console::VirtualKeyCode;
+
+pub const KEYMAP_FILE_NAME: &str = "keymap.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveNorth,
+    MoveSouth,
+    MoveWest,
+    MoveEast,
+    MoveNorthWest,
+    MoveNorthEast,
+    MoveSouthWest,
+    MoveSouthEast,
+    Wait,
+    OpenDoor,
+    CloseDoor,
+    UseStairs,
+    PickUp,
+    Inventory,
+    Save,
+    Quit,
+    QuitWithoutSaving,
+    MenuUp,
+    MenuDown,
+    MenuSelect,
+    MenuBack,
+    DropItem,
+}
+
+/// Every action with its name in the keymap file, how it is described on
+/// the options screen, and its default keys
+pub const ACTIONS: &[(Action, &str, &str, &[VirtualKeyCode])] = &[
+    (
+        Action::MoveNorth,
+        "move_north",
+        "Move north",
+        &[
+            VirtualKeyCode::Up,
+            VirtualKeyCode::K,
+            VirtualKeyCode::Numpad8,
+        ],
+    ),
+    (
+        Action::MoveSouth,
+        "move_south",
+        "Move south",
+        &[
+            VirtualKeyCode::Down,
+            VirtualKeyCode::J,
+            VirtualKeyCode::Numpad2,
+        ],
+    ),
+    (
+        Action::MoveWest,
+        "move_west",
+        "Move west",
+        &[
+            VirtualKeyCode::Left,
+            VirtualKeyCode::H,
+            VirtualKeyCode::Numpad4,
+        ],
+    ),
+    (
+        Action::MoveEast,
+        "move_east",
+        "Move east",
+        &[
+            VirtualKeyCode::Right,
+            VirtualKeyCode::L,
+            VirtualKeyCode::Numpad6,
+        ],
+    ),
+    (
+        Action::MoveNorthWest,
+        "move_north_west",
+        "Move north-west",
+        &[VirtualKeyCode::Y, VirtualKeyCode::Numpad7],
+    ),
+    (
+        Action::MoveNorthEast,
+        "move_north_east",
+        "Move north-east",
+        &[VirtualKeyCode::U, VirtualKeyCode::Numpad9],
+    ),
+    (
+        Action::MoveSouthWest,
+        "move_south_west",
+        "Move south-west",
+        &[VirtualKeyCode::B, VirtualKeyCode::Numpad1],
+    ),
+    (
+        Action::MoveSouthEast,
+        "move_south_east",
+        "Move south-east",
+        &[VirtualKeyCode::N, VirtualKeyCode::Numpad3],
+    ),
+    (
+        Action::Wait,
+        "wait",
+        "Wait a turn",
+        &[VirtualKeyCode::Period, VirtualKeyCode::Numpad5],
+    ),
+    (
+        Action::OpenDoor,
+        "open_door",
+        "Open door",
+        &[VirtualKeyCode::O],
+    ),
+    (
+        Action::CloseDoor,
+        "close_door",
+        "Close door",
+        &[VirtualKeyCode::C],
+    ),
+    (
+        Action::UseStairs,
+        "use_stairs",
+        "Use stairs",
+        &[VirtualKeyCode::Space, VirtualKeyCode::NumpadEnter],
+    ),
+    (
+        Action::PickUp,
+        "pick_up",
+        "Pick up",
+        &[VirtualKeyCode::G, VirtualKeyCode::Comma],
+    ),
+    (
+        Action::Inventory,
+        "inventory",
+        "Inventory",
+        &[VirtualKeyCode::I, VirtualKeyCode::D],
+    ),
+    (Action::Save, "save", "Save game", &[VirtualKeyCode::S]),
+    (
+        Action::Quit,
+        "quit",
+        "Save and quit",
+        &[VirtualKeyCode::Escape],
+    ),
+    (
+        Action::QuitWithoutSaving,
+        "quit_without_saving",
+        "Quit without saving",
+        &[VirtualKeyCode::Q],
+    ),
+    (
+        Action::MenuUp,
+        "menu_up",
+        "Menu up",
+        &[VirtualKeyCode::Up, VirtualKeyCode::Left],
+    ),
+    (
+        Action::MenuDown,
+        "menu_down",
+        "Menu down",
+        &[VirtualKeyCode::Down, VirtualKeyCode::Right],
+    ),
+    (
+        Action::MenuSelect,
+        "menu_select",
+        "Menu select",
+        &[
+            VirtualKeyCode::Space,
+            VirtualKeyCode::NumpadEnter,
+            VirtualKeyCode::Return,
+        ],
+    ),
+    (
+        Action::MenuBack,
+        "menu_back",
+        "Menu back",
+        &[VirtualKeyCode::Escape],
+    ),
+    (
+        Action::DropItem,
+        "drop_item",
+        "Drop item",
+        &[VirtualKeyCode::D],
+    ),
+];
+
+/// What can be done in game. No key does two of these.
+pub const GAME_ACTIONS: &[Action] = &[
+    Action::MoveNorth,
+    Action::MoveSouth,
+    Action::MoveWest,
+    Action::MoveEast,
+    Action::MoveNorthWest,
+    Action::MoveNorthEast,
+    Action::MoveSouthWest,
+    Action::MoveSouthEast,
+    Action::Wait,
+    Action::OpenDoor,
+    Action::CloseDoor,
+    Action::UseStairs,
+    Action::PickUp,
+    Action::Inventory,
+    Action::Save,
+    Action::Quit,
+    Action::QuitWithoutSaving,
+];
+
+/// What can be done in menus, the inventory included. No key does two of
+/// these either, but they can share keys with the game actions.
+pub const MENU_ACTIONS: &[Action] = &[
+    Action::MenuUp,
+    Action::MenuDown,
+    Action::MenuSelect,
+    Action::MenuBack,
+    Action::DropItem,
+];
+
+/// Menu actions that always need a key, as without them there would be no
+/// way back through the menus to fix the bindings
+pub const REQUIRED_ACTIONS: &[Action] = &[
+    Action::MenuUp,
+    Action::MenuDown,
+    Action::MenuSelect,
+    Action::MenuBack,
+];
+
+/// The keys that can be bound. They go by their `VirtualKeyCode` names in
+/// the keymap file.
+const KEYS: &[VirtualKeyCode] = &[
+    VirtualKeyCode::A,
+    VirtualKeyCode::B,
+    VirtualKeyCode::C,
+    VirtualKeyCode::D,
+    VirtualKeyCode::E,
+    VirtualKeyCode::F,
+    VirtualKeyCode::G,
+    VirtualKeyCode::H,
+    VirtualKeyCode::I,
+    VirtualKeyCode::J,
+    VirtualKeyCode::K,
+    VirtualKeyCode::L,
+    VirtualKeyCode::M,
+    VirtualKeyCode::N,
+    VirtualKeyCode::O,
+    VirtualKeyCode::P,
+    VirtualKeyCode::Q,
+    VirtualKeyCode::R,
+    VirtualKeyCode::S,
+    VirtualKeyCode::T,
+    VirtualKeyCode::U,
+    VirtualKeyCode::V,
+    VirtualKeyCode::W,
+    VirtualKeyCode::X,
+    VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Key0,
+    VirtualKeyCode::Key1,
+    VirtualKeyCode::Key2,
+    VirtualKeyCode::Key3,
+    VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5,
+    VirtualKeyCode::Key6,
+    VirtualKeyCode::Key7,
+    VirtualKeyCode::Key8,
+    VirtualKeyCode::Key9,
+    VirtualKeyCode::Numpad0,
+    VirtualKeyCode::Numpad1,
+    VirtualKeyCode::Numpad2,
+    VirtualKeyCode::Numpad3,
+    VirtualKeyCode::Numpad4,
+    VirtualKeyCode::Numpad5,
+    VirtualKeyCode::Numpad6,
+    VirtualKeyCode::Numpad7,
+    VirtualKeyCode::Numpad8,
+    VirtualKeyCode::Numpad9,
+    VirtualKeyCode::NumpadEnter,
+    VirtualKeyCode::NumpadAdd,
+    VirtualKeyCode::NumpadSubtract,
+    VirtualKeyCode::NumpadMultiply,
+    VirtualKeyCode::NumpadDivide,
+    VirtualKeyCode::NumpadDecimal,
+    VirtualKeyCode::Up,
+    VirtualKeyCode::Down,
+    VirtualKeyCode::Left,
+    VirtualKeyCode::Right,
+    VirtualKeyCode::Home,
+    VirtualKeyCode::End,
+    VirtualKeyCode::PageUp,
+    VirtualKeyCode::PageDown,
+    VirtualKeyCode::Insert,
+    VirtualKeyCode::Delete,
+    VirtualKeyCode::Space,
+    VirtualKeyCode::Return,
+    VirtualKeyCode::Escape,
+    VirtualKeyCode::Back,
+    VirtualKeyCode::Tab,
+    VirtualKeyCode::Comma,
+    VirtualKeyCode::Period,
+    VirtualKeyCode::Slash,
+    VirtualKeyCode::Semicolon,
+    VirtualKeyCode::Apostrophe,
+    VirtualKeyCode::LBracket,
+    VirtualKeyCode::RBracket,
+    VirtualKeyCode::Minus,
+    VirtualKeyCode::Equals,
+    VirtualKeyCode::Grave,
+    VirtualKeyCode::Backslash,
+];
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        self.entry().1
+    }
+
+    pub fn label(&self) -> &'static str {
+        self.entry().2
+    }
+
+    fn entry(
+        &self,
+    ) -> &'static (
+        Action,
+        &'static str,
+        &'static str,
+        &'static [VirtualKeyCode],
+    ) {
+        ACTIONS
+            .iter()
+            .find(|(action, ..)| action == self)
+            .expect("every action has an entry")
+    }
+
+    /// The actions this one can't share a key with
+    fn group(&self) -> &'static [Action] {
+        if MENU_ACTIONS.contains(self) {
+            MENU_ACTIONS
+        } else {
+            GAME_ACTIONS
+        }
+    }
+}
+
+fn action_named(name: &str) -> Option<Action> {
+    ACTIONS
+        .iter()
+        .find(|(_, action_name, ..)| *action_name == name)
+        .map(|(action, ..)| *action)
+}
+
+fn key_named(name: &str) -> Option<VirtualKeyCode> {
+    KEYS.iter().find(|key| key_name(key) == name).copied()
+}
+
+pub fn key_name(key: &VirtualKeyCode) -> String {
+    format!("{:?}", key)
+}
+
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<Action, Vec<VirtualKeyCode>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let bindings = ACTIONS
+            .iter()
+            .map(|(action, _, _, keys)| (*action, keys.to_vec()))
+            .collect();
+        Keymap { bindings }
+    }
+}
+
+impl Keymap {
+    /// Reads the keymap file over the default bindings. A missing file just
+    /// means the defaults.
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Keymap> {
+        let mut keymap = Keymap::default();
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(keymap),
+            Err(err) => return Err(err.into()),
+        };
+        let file: HashMap<String, Vec<String>> = toml::from_str(&contents)
+            .with_context(|| format!("couldn't parse {}", path.as_ref().display()))?;
+        let mut entries = vec![];
+        for (action_name, key_names) in file {
+            let action = action_named(&action_name)
+                .with_context(|| format!("unknown action '{}'", action_name))?;
+            let keys = key_names
+                .iter()
+                .map(|key_name| {
+                    key_named(key_name).with_context(|| format!("unknown key '{}'", key_name))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            entries.push((action, keys));
+        }
+        // In the order of the options screen, so the same clash is always
+        // the one reported
+        entries.sort_by_key(|(action, _)| ACTIONS.iter().position(|(other, ..)| other == action));
+        for (i, (action, keys)) in entries.iter().enumerate() {
+            for (other, other_keys) in &entries[..i] {
+                if !action.group().contains(other) {
+                    continue;
+                }
+                if let Some(key) = keys.iter().find(|key| other_keys.contains(key)) {
+                    anyhow::bail!(
+                        "'{}' is bound to both '{}' and '{}'",
+                        key_name(key),
+                        other.name(),
+                        action.name()
+                    );
+                }
+            }
+        }
+        for (action, keys) in entries {
+            keymap.bindings.insert(action, vec![]);
+            for key in keys {
+                keymap.take_key(action, key);
+            }
+        }
+        if let Some(action) = keymap.unbound_required() {
+            anyhow::bail!("'{}' has no key", action.name());
+        }
+        Ok(keymap)
+    }
+
+    /// Writes every binding out, in the same order as the options screen
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let mut contents = String::new();
+        for (action, ..) in ACTIONS {
+            let keys = self
+                .keys(*action)
+                .iter()
+                .map(|key| toml::Value::String(key_name(key)))
+                .collect();
+            contents.push_str(&format!(
+                "{} = {}\n",
+                action.name(),
+                toml::Value::Array(keys)
+            ));
+        }
+        std::fs::write(&path, contents)
+            .with_context(|| format!("couldn't write {}", path.as_ref().display()))
+    }
+
+    /// The first of the actions that one of the pressed keys is bound to
+    pub fn action(&self, keycodes: &HashSet<VirtualKeyCode>, actions: &[Action]) -> Option<Action> {
+        actions
+            .iter()
+            .find(|action| self.keys(**action).iter().any(|key| keycodes.contains(key)))
+            .copied()
+    }
+
+    pub fn keys(&self, action: Action) -> &[VirtualKeyCode] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds a key to an action, taking it away from anything else it did.
+    /// Returns the required action it would leave without a key, without
+    /// binding anything, if there is one.
+    pub fn bind(&mut self, action: Action, key: VirtualKeyCode) -> Result<(), Action> {
+        if let Some(&other) = action.group().iter().find(|other| {
+            **other != action && REQUIRED_ACTIONS.contains(other) && self.keys(**other) == [key]
+        }) {
+            return Err(other);
+        }
+        self.take_key(action, key);
+        Ok(())
+    }
+
+    /// Takes every key away from an action. Required actions keep theirs,
+    /// and are returned as the error.
+    pub fn clear(&mut self, action: Action) -> Result<(), Action> {
+        if REQUIRED_ACTIONS.contains(&action) {
+            return Err(action);
+        }
+        self.bindings.insert(action, vec![]);
+        Ok(())
+    }
+
+    /// The first required action that has no key, if any
+    pub fn unbound_required(&self) -> Option<Action> {
+        REQUIRED_ACTIONS
+            .iter()
+            .find(|action| self.keys(**action).is_empty())
+            .copied()
+    }
+
+    fn take_key(&mut self, action: Action, key: VirtualKeyCode) {
+        for other in action.group() {
+            if let Some(keys) = self.bindings.get_mut(other) {
+                keys.retain(|bound| *bound != key);
+            }
+        }
+        self.bindings.entry(action).or_default().push(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_str(name: &str, contents: &str) -> anyhow::Result<Keymap> {
+        let path = std::env::temp_dir().join(format!(
+            "rust-roguelike-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, contents).unwrap();
+        let keymap = Keymap::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        keymap
+    }
+
+    #[test]
+    fn the_shipped_keymap_loads() {
+        Keymap::load(KEYMAP_FILE_NAME).unwrap();
+    }
+
+    #[test]
+    fn a_key_bound_twice_is_refused() {
+        let err = load_str(
+            "bound_twice",
+            "wait = [\"Space\"]\nmove_north = [\"Space\"]\n",
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(err, "'Space' is bound to both 'move_north' and 'wait'");
+    }
+
+    #[test]
+    fn a_key_taken_from_a_default_moves() {
+        let keymap = load_str("taken", "wait = [\"K\"]\n").unwrap();
+        assert_eq!(keymap.keys(Action::Wait), [VirtualKeyCode::K]);
+        assert!(!keymap.keys(Action::MoveNorth).contains(&VirtualKeyCode::K));
+    }
+}
@@ -0,0 +1,16 @@
+pub mod component;
+pub mod entity;
+mod fov;
+pub mod game;
+pub mod game_object;
+pub mod integrity;
+pub mod map_gen;
+pub mod meta;
+mod navigation;
+pub mod profiler;
+pub mod schema;
+pub mod system;
+#[cfg(test)]
+mod test_fixtures;
+
+pub use game::{Game, Recording, StepResult, Storage};
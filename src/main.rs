@@ -1,64 +1,35 @@
-mod component;
 mod console;
-mod entity;
-mod game_object;
-mod map_gen;
-mod meta;
-mod profiler;
-mod system;
+mod keymap;
+mod ui;
 
-use crate::console::Console;
-use console::ClickEvent;
+use std::io::Write;
+
+use console::{ClickEvent, Console};
 use ggez::{conf::WindowMode, ContextBuilder, GameResult};
-use map_gen::Tile;
-use profiler::TurnProfiler;
-use rand::Rng;
-use std::{path::Path, sync::Mutex};
+use rust_roguelike::{component, meta, Game, StepResult, Storage};
 
 const DESIRED_FPS: u32 = 60;
-
-fn add_pcg_randint_function(
-    db: &rusqlite::Connection,
-    rng: &'static Mutex<meta::GameRng>,
-) -> rusqlite::Result<()> {
-    use rusqlite::functions::FunctionFlags;
-    db.create_scalar_function(
-        "pcg_randint",
-        2,
-        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY,
-        move |ctx| {
-            let min = ctx.get::<i32>(0)?;
-            let max = ctx.get::<i32>(1)?;
-            let spread = (max - min).abs();
-            let start = min.min(max);
-            let num = rng.lock().unwrap().gen_range(start..=start + spread);
-            Ok(num)
-        },
-    )?;
-    db.create_scalar_function(
-        "pcg_random",
-        0,
-        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY,
-        move |_ctx| {
-            let num = rng.lock().unwrap().gen::<i64>();
-            Ok(num)
-        },
-    )
-}
+const ERROR_LOG_FILE_NAME: &str = "error.log";
 
 fn main() -> anyhow::Result<()> {
-    let rng = Box::leak(Box::new(Mutex::new(meta::init_rng())));
-
     // Placeholder for game engine init
     let (mut ctx, event_loop) = ContextBuilder::new("rust_roguelike", "Yours Truly")
         .window_mode(WindowMode {
-            width: (console::PIXEL_WIDTH) * meta::CONSOLE_WIDTH as f32,
-            height: (console::PIXEL_HEIGHT) * meta::CONSOLE_HEIGHT as f32,
+            width: (console::PIXEL_WIDTH) * ui::CONSOLE_WIDTH as f32,
+            height: (console::PIXEL_HEIGHT) * ui::CONSOLE_HEIGHT as f32,
             ..Default::default()
         })
         .build()?;
 
-    let main_menu = meta::main_menu();
+    let mut renderer = ui::Renderer::new();
+    let keymap = keymap::Keymap::load(keymap::KEYMAP_FILE_NAME).unwrap_or_else(|err| {
+        renderer.set_notice(Some(format!(
+            "Using the default keys, as the keymap didn't load: {:#}",
+            err
+        )));
+        keymap::Keymap::default()
+    });
+    let main_menu = ui::main_menu();
     let console = Console::new(&mut ctx);
     ggez::event::run(
         ctx,
@@ -66,9 +37,9 @@ fn main() -> anyhow::Result<()> {
         GgezState {
             console,
             state: State {
-                rng,
-                renderer: meta::Renderer::new(),
-                mode: meta::GameMode::MainMenu(main_menu),
+                renderer,
+                mode: ui::GameMode::MainMenu(main_menu),
+                keymap,
             },
         },
     );
@@ -80,115 +51,37 @@ struct GgezState {
 }
 
 struct State {
-    mode: meta::GameMode,
-    renderer: meta::Renderer,
-    rng: &'static Mutex<meta::GameRng>,
-}
-
-fn new_game<P: AsRef<Path>>(
-    rng: &'static Mutex<meta::GameRng>,
-    path: P,
-    is_creative: bool,
-    mut dungeon_generator: impl map_gen::Generator,
-) -> anyhow::Result<meta::GameMode> {
-    std::fs::remove_file(&path)?;
-    let db = open_db(path, rng)?;
-
-    db.execute_batch("BEGIN TRANSACTION")?;
-    entity::create_table(&db)?;
-    component::create_tables(&db)?;
-
-    let player = game_object::init_player(&db, is_creative)?;
-    let initial_dungeon = dungeon_generator.generate(
-        &mut rng.lock().unwrap(),
-        meta::WORLD_WIDTH,
-        meta::WORLD_HEIGHT,
-    );
-
-    for (tile, x, y) in initial_dungeon.iter() {
-        let pos = game_object::WorldPoint { x, y };
-
-        if tile == Tile::Unused {
-            continue;
-        } else if tile == Tile::Floor || tile == Tile::Corridor {
-            game_object::init_floor(&db, pos)?;
-        } else if tile == Tile::Wall {
-            game_object::init_wall(&db, "#", pos)?;
-        } else if tile == Tile::ClosedDoor || tile == Tile::OpenDoor {
-            game_object::init_floor(&db, pos)?; // doors aren't supported at this time
-        } else if tile == Tile::DownStairs {
-            game_object::init_floor(&db, pos)?;
-            let down_stairs = entity::create(&db)?;
-            component::actor::set(
-                &db,
-                component::actor::Actor {
-                    entity: down_stairs,
-                    tile: ">".into(),
-                    pos,
-                    color: game_object::PLAYER_COLOR,
-                    plane: game_object::Plane::Objects,
-                },
-            )?;
-            component::transition::set(&db, down_stairs, game_object::WIN_LEVEL)?;
-        } else if tile == Tile::UpStairs {
-            game_object::init_floor(&db, pos)?;
-            // Player spawns where the up staircase would be
-            component::actor::set(
-                &db,
-                component::actor::Actor {
-                    entity: player,
-                    tile: "@".into(),
-                    pos,
-                    color: game_object::STAIR_COLOR,
-                    plane: game_object::Plane::Player,
-                },
-            )?;
-        }
-    }
-    db.execute_batch("COMMIT TRANSACTION")?;
-
-    let profiler = TurnProfiler::new(&db)?;
-    Ok(meta::GameMode::InGame {
-        db,
-        player,
-        profiler,
-        is_creative,
-        selected_point: None,
-    })
-}
-
-fn load_game<P: AsRef<Path>>(
-    rng: &'static Mutex<meta::GameRng>,
-    path: P,
-) -> anyhow::Result<meta::GameMode> {
-    let db = open_db(path, rng)?;
-    let player = entity::load_player(&db)?;
-    let is_creative = component::player::is_creative(&db)?;
-    let profiler = TurnProfiler::new(&db)?;
-    Ok(meta::GameMode::InGame {
-        db,
-        player,
-        profiler,
-        is_creative,
-        selected_point: None,
-    })
+    mode: ui::GameMode,
+    renderer: ui::Renderer,
+    keymap: keymap::Keymap,
 }
 
 impl ggez::event::EventHandler<ggez::GameError> for GgezState {
     fn update(&mut self, ctx: &mut ggez::Context) -> GameResult {
         while ctx.time.check_update_time(DESIRED_FPS) {
-            self.state
-                .tick(&mut self.console, ctx)
-                .expect("Unexpected error during game tick")
+            if let Err(err) = self.state.tick(&mut self.console, ctx) {
+                self.state.fail(err);
+            }
         }
         Ok(())
     }
 
+    /// Saves the game being played when the window is closed
+    fn quit_event(&mut self, _ctx: &mut ggez::Context) -> Result<bool, ggez::GameError> {
+        if let Some(Err(err)) = self.state.playing().map(Game::save) {
+            log_error(&anyhow::Error::from(err).context("couldn't save on quitting"));
+        }
+        Ok(false)
+    }
+
     fn draw(&mut self, ctx: &mut ggez::Context) -> GameResult {
-        self.state
+        let drawn = self
+            .state
             .renderer
-            .draw(&self.state.mode, &mut self.console, ctx)
-            .expect("Unexpected error during game draw");
+            .draw(&self.state.mode, &mut self.console, ctx);
+        if let Err(err) = drawn {
+            self.state.fail(err);
+        }
         Ok(())
     }
 }
@@ -198,48 +91,243 @@ impl State {
         // Game loop.
         let keys = console.key_presses(ctx);
         match self.mode {
-            meta::GameMode::MainMenu(ref mut menu) => {
-                let selected = meta::keydown_handler(&keys, menu);
+            ui::GameMode::MainMenu(ref mut menu) => {
+                let selected = ui::keydown_handler(&keys, menu, &self.keymap);
                 match selected {
-                    meta::MenuResult::None => {}
-                    meta::MenuResult::Updated => {
+                    ui::MenuResult::None => {}
+                    ui::MenuResult::Updated => {
+                        self.renderer.mark_dirty();
+                    }
+                    ui::MenuResult::Selected(ui::NEW_GAME) => {
+                        self.mode = ui::GameMode::SlotSelect(ui::SlotSelect::new(
+                            ui::SlotPurpose::NewGame(ui::NewGame {
+                                is_creative: false,
+                                seed: meta::new_seed(),
+                            }),
+                        )?);
+                        self.renderer.mark_dirty();
+                    }
+                    ui::MenuResult::Selected(ui::NEW_SEEDED_GAME) => {
+                        self.mode = ui::GameMode::SeedEntry(String::new());
                         self.renderer.mark_dirty();
                     }
-                    meta::MenuResult::Selected(meta::NEW_GAME) => {
-                        self.mode = new_game(
-                            self.rng,
-                            meta::SAVE_FILE_NAME,
-                            false,
-                            map_gen::DefaultGenerator::new(),
-                        )?;
+                    ui::MenuResult::Selected(ui::LOAD_GAME) => {
+                        self.mode =
+                            ui::GameMode::SlotSelect(ui::SlotSelect::new(ui::SlotPurpose::Load)?);
                         self.renderer.mark_dirty();
                     }
-                    meta::MenuResult::Selected(meta::LOAD_GAME) => {
-                        self.mode = load_game(self.rng, meta::SAVE_FILE_NAME)?;
+                    ui::MenuResult::Selected(ui::WATCH_REPLAY) => {
+                        self.mode =
+                            ui::GameMode::SlotSelect(ui::SlotSelect::new(ui::SlotPurpose::Replay)?);
                         self.renderer.mark_dirty();
                     }
-                    meta::MenuResult::Selected(meta::CREATIVE_MODE) => {
-                        self.mode = new_game(
-                            self.rng,
-                            meta::SAVE_FILE_NAME,
-                            true,
-                            map_gen::EmptyGenerator,
-                        )?;
+                    ui::MenuResult::Selected(ui::OPTIONS) => {
+                        self.mode = ui::GameMode::Options(ui::Options::new(self.keymap.clone()));
                         self.renderer.mark_dirty();
                     }
-                    meta::MenuResult::Selected(selected) => {
+                    ui::MenuResult::Selected(ui::CREATIVE_MODE) => {
+                        self.mode = ui::GameMode::SlotSelect(ui::SlotSelect::new(
+                            ui::SlotPurpose::NewGame(ui::NewGame {
+                                is_creative: true,
+                                seed: meta::new_seed(),
+                            }),
+                        )?);
+                        self.renderer.mark_dirty();
+                    }
+                    ui::MenuResult::Selected(selected) => {
                         println!("Unexpected menu item '{}'. This is a bug", selected)
                     }
-                    meta::MenuResult::Back => {
+                    ui::MenuResult::Back => {
                         console.quit(ctx);
                     }
                 }
             }
-            meta::GameMode::InGame {
-                ref db,
-                player,
-                mut profiler,
-                is_creative: _is_creative,
+            ui::GameMode::SeedEntry(ref mut input) => {
+                match ui::seed_entry_keydown_handler(&keys, input) {
+                    ui::SeedEntryResult::None => {}
+                    ui::SeedEntryResult::Updated => {
+                        self.renderer.mark_dirty();
+                    }
+                    ui::SeedEntryResult::Start(seed) => {
+                        self.mode = ui::GameMode::SlotSelect(ui::SlotSelect::new(
+                            ui::SlotPurpose::NewGame(ui::NewGame {
+                                is_creative: false,
+                                seed,
+                            }),
+                        )?);
+                        self.renderer.mark_dirty();
+                    }
+                    ui::SeedEntryResult::Back => {
+                        self.mode = ui::GameMode::MainMenu(ui::main_menu());
+                        self.renderer.mark_dirty();
+                    }
+                }
+            }
+            ui::GameMode::SlotSelect(ref mut select) => {
+                match ui::slot_keydown_handler(&keys, select, &self.keymap) {
+                    ui::SlotResult::None => {}
+                    ui::SlotResult::Updated => {
+                        self.renderer.mark_dirty();
+                    }
+                    ui::SlotResult::NewSlot => {
+                        if let ui::SlotPurpose::NewGame(new_game) = select.purpose {
+                            self.mode = ui::GameMode::SlotNameEntry {
+                                new_game,
+                                input: String::new(),
+                            };
+                            self.renderer.mark_dirty();
+                        }
+                    }
+                    ui::SlotResult::Selected(slot) => {
+                        match select.purpose {
+                            ui::SlotPurpose::NewGame(new_game) => {
+                                self.mode =
+                                    ui::GameMode::Confirm(ui::Confirm::overwrite(slot, new_game));
+                            }
+                            ui::SlotPurpose::Load if slot.backup_turn.is_some() => {
+                                self.mode = ui::GameMode::Confirm(ui::Confirm::restore(slot));
+                            }
+                            ui::SlotPurpose::Load => self.mode = load(&slot)?,
+                            ui::SlotPurpose::Replay => match Game::replay(&slot.path) {
+                                Ok(Some((game, commands))) => {
+                                    self.mode =
+                                        ui::GameMode::Replay(ui::Replay::new(game, commands));
+                                }
+                                Ok(None) => return Ok(()),
+                                Err(err) => {
+                                    self.mode = ui::GameMode::LoadFailed(ui::LoadFailed::new(
+                                        select.purpose,
+                                        &slot.name,
+                                        &err,
+                                    ));
+                                }
+                            },
+                        }
+                        self.renderer.mark_dirty();
+                    }
+                    ui::SlotResult::Delete(slot) => {
+                        self.mode =
+                            ui::GameMode::Confirm(ui::Confirm::delete(slot, select.purpose));
+                        self.renderer.mark_dirty();
+                    }
+                    ui::SlotResult::Back => {
+                        self.mode = ui::GameMode::MainMenu(ui::main_menu());
+                        self.renderer.mark_dirty();
+                    }
+                }
+            }
+            ui::GameMode::SlotNameEntry {
+                new_game,
+                ref mut input,
+            } => match ui::name_entry_keydown_handler(&keys, input) {
+                ui::NameEntryResult::None => {}
+                ui::NameEntryResult::Updated => {
+                    self.renderer.mark_dirty();
+                }
+                ui::NameEntryResult::Done(name) => {
+                    self.mode = match meta::SaveSlot::find(&name)? {
+                        Some(slot) => ui::GameMode::Confirm(ui::Confirm::overwrite(slot, new_game)),
+                        None => ui::start(&meta::slot_path(&name), new_game)?,
+                    };
+                    self.renderer.mark_dirty();
+                }
+                ui::NameEntryResult::Back => {
+                    self.mode = ui::GameMode::SlotSelect(ui::SlotSelect::new(
+                        ui::SlotPurpose::NewGame(new_game),
+                    )?);
+                    self.renderer.mark_dirty();
+                }
+            },
+            ui::GameMode::Confirm(_) => {
+                let answer = ui::confirm_keydown_handler(&keys);
+                if let ui::ConfirmResult::None = answer {
+                    return Ok(());
+                }
+                let is_yes = matches!(answer, ui::ConfirmResult::Yes);
+                // Anything that fails from here on leaves the main menu up
+                let confirm = match std::mem::replace(
+                    &mut self.mode,
+                    ui::GameMode::MainMenu(ui::main_menu()),
+                ) {
+                    ui::GameMode::Confirm(confirm) => confirm,
+                    mode => {
+                        self.mode = mode;
+                        return Ok(());
+                    }
+                };
+                self.mode = match confirm.action {
+                    ui::ConfirmAction::Overwrite { slot, new_game } if is_yes => {
+                        slot.delete()?;
+                        ui::start(&slot.path, new_game)?
+                    }
+                    ui::ConfirmAction::Overwrite { new_game, .. } => ui::GameMode::SlotSelect(
+                        ui::SlotSelect::new(ui::SlotPurpose::NewGame(new_game))?,
+                    ),
+                    ui::ConfirmAction::Delete { slot, purpose } => {
+                        if is_yes {
+                            slot.delete()?;
+                        }
+                        ui::GameMode::SlotSelect(ui::SlotSelect::new(purpose)?)
+                    }
+                    ui::ConfirmAction::Abandon { game } if is_yes => {
+                        if let Some(path) = &game.path {
+                            meta::discard_unsaved_backups(path)?;
+                        }
+                        ui::GameMode::MainMenu(ui::main_menu())
+                    }
+                    ui::ConfirmAction::Abandon { game } => ui::play(game)?,
+                    ui::ConfirmAction::Restore { slot } => {
+                        if is_yes {
+                            meta::restore_backup(&slot.path, 0)?;
+                        }
+                        load(&slot)?
+                    }
+                };
+                self.renderer.mark_dirty();
+            }
+            ui::GameMode::Inventory {
+                ref mut game,
+                ref mut menu,
+                ref items,
+            } => match ui::inventory_keydown_handler(&keys, menu, items, &self.keymap) {
+                ui::InGameResult::Command(command) => {
+                    let result = game.step(command)?;
+                    if let Some(err) = game.take_autosave_failure() {
+                        log_error(&err.context("couldn't autosave"));
+                    }
+                    self.finish_step(result)?;
+                }
+                ui::InGameResult::CloseInventory => {
+                    self.close_inventory();
+                }
+                ui::InGameResult::Updated => {
+                    self.renderer.mark_dirty();
+                }
+                _ => {}
+            },
+            ui::GameMode::LoadFailed(ref failed) => {
+                if !keys.is_empty() {
+                    self.mode = ui::GameMode::SlotSelect(ui::SlotSelect::new(failed.purpose)?);
+                    self.renderer.mark_dirty();
+                }
+            }
+            ui::GameMode::Error(ref mut screen) => {
+                match ui::error_keydown_handler(&keys, screen, &self.keymap) {
+                    ui::ErrorResult::None => {}
+                    ui::ErrorResult::Updated => self.renderer.mark_dirty(),
+                    ui::ErrorResult::Retry => {
+                        self.mode =
+                            std::mem::replace(&mut *screen.previous, ui::GameMode::Transitioning);
+                        self.renderer.mark_dirty();
+                    }
+                    ui::ErrorResult::MainMenu => {
+                        self.mode = ui::GameMode::MainMenu(ui::main_menu());
+                        self.renderer.mark_dirty();
+                    }
+                }
+            }
+            ui::GameMode::InGame {
+                ref mut game,
                 ref mut selected_point,
             } => {
                 let clicks = console.clicks(ctx);
@@ -247,61 +335,227 @@ impl State {
                     *selected_point = Some(pos);
                     self.renderer.mark_dirty();
                 }
-                let new_mode = meta::in_game_keydown_handler(db, &keys, player)?;
-
-                if let Some(meta::GameMode::WonGame) = new_mode {
-                    self.mode = meta::GameMode::WonGame;
-                    self.renderer.mark_dirty();
-                } else if component::player::outstanding_turns(db)? > 0 {
-                    db.execute_batch("BEGIN TRANSACTION")?;
-                    let mut turn = profiler.start();
-                    system::apply_ai(db)?;
-                    turn.split("ai");
-                    system::move_actors(db)?;
-                    turn.split("movement");
-                    component::player::pass_time(db, 1)?;
-                    turn.split("time");
-                    system::apply_regen(db)?;
-                    turn.split("regen");
-                    for _ in 0..25 {
-                        game_object::generate_particles(db, 25)?;
-                    }
-                    turn.split("particles");
-                    for _ in 0..5 {
-                        game_object::generate_enemies(db, 10)?;
-                    }
-                    turn.split("enemies");
-                    system::cull_dead(db)?;
-                    system::cull_ephemeral(db)?;
-                    turn.split("culling");
-                    let turn_num = component::player::turns_passed(db)?;
-
-                    let actor_count = component::actor::count(db)?;
-                    db.execute_batch("COMMIT TRANSACTION")?;
-
-                    profiler.end(db, turn_num, turn, actor_count)?;
-                    self.renderer.mark_dirty();
+                match ui::in_game_keydown_handler(&keys, &self.keymap) {
+                    ui::InGameResult::Command(command) => {
+                        let result = game.step(command)?;
+                        if let Some(err) = game.take_autosave_failure() {
+                            log_error(&err.context("couldn't autosave"));
+                        }
+                        self.finish_step(result)?;
+                    }
+                    ui::InGameResult::OpenInventory => {
+                        let (menu, items) = ui::inventory_menu(game)?;
+                        if let Some(game) = self.take_game() {
+                            self.mode = ui::GameMode::Inventory { game, menu, items };
+                        }
+                        self.renderer.mark_dirty();
+                    }
+                    ui::InGameResult::Save => game.save()?,
+                    ui::InGameResult::Quit => {
+                        game.save()?;
+                        self.mode = ui::GameMode::MainMenu(ui::main_menu());
+                        self.renderer.mark_dirty();
+                    }
+                    ui::InGameResult::QuitWithoutSaving => {
+                        if let Some(game) = self.take_game() {
+                            self.mode = ui::GameMode::Confirm(ui::Confirm::abandon(game));
+                        }
+                        self.renderer.mark_dirty();
+                    }
+                    _ => {}
                 }
             }
-            meta::GameMode::WonGame => {
-                meta::won_game_keydown_handler(&keys, &mut self.mode);
+            ui::GameMode::Replay(ref mut replay) => {
+                match ui::replay_keydown_handler(&keys, replay) {
+                    ui::ReplayResult::None => {}
+                    ui::ReplayResult::Updated => {
+                        self.renderer.mark_dirty();
+                    }
+                    ui::ReplayResult::Back => {
+                        self.mode = ui::GameMode::MainMenu(ui::main_menu());
+                        self.renderer.mark_dirty();
+                        return Ok(());
+                    }
+                }
+                replay.ticks_waited += 1;
+                if replay.finished || replay.ticks_waited < DESIRED_FPS / replay.turns_per_second {
+                    return Ok(());
+                }
+                replay.ticks_waited = 0;
+                match replay.commands.pop_front() {
+                    Some((turn, command)) => {
+                        if replay.diverged_at.is_none()
+                            && turn != component::player::turns_passed(&replay.game.db)?
+                        {
+                            replay.diverged_at = Some(turn);
+                        }
+                        match replay.game.step(command)? {
+                            StepResult::Won | StepResult::Died(_) => replay.finished = true,
+                            StepResult::Ignored | StepResult::Continue => {}
+                        }
+                    }
+                    None => replay.finished = true,
+                }
                 self.renderer.mark_dirty();
             }
+            ui::GameMode::Options(ref mut options) => {
+                match ui::options_keydown_handler(&keys, options, &self.keymap) {
+                    ui::OptionsResult::None => {}
+                    ui::OptionsResult::Updated => {
+                        self.renderer.mark_dirty();
+                    }
+                    ui::OptionsResult::Save => {
+                        options.keymap.save(keymap::KEYMAP_FILE_NAME)?;
+                        self.keymap = options.keymap.clone();
+                        self.renderer.set_notice(None);
+                        self.mode = ui::GameMode::MainMenu(ui::main_menu());
+                        self.renderer.mark_dirty();
+                    }
+                    ui::OptionsResult::Back => {
+                        self.mode = ui::GameMode::MainMenu(ui::main_menu());
+                        self.renderer.mark_dirty();
+                    }
+                }
+            }
+            ui::GameMode::WonGame | ui::GameMode::Dead(_) => {
+                ui::game_over_keydown_handler(&keys, &mut self.mode);
+                self.renderer.mark_dirty();
+            }
+            ui::GameMode::Transitioning => {}
         }
         anyhow::Result::Ok(())
     }
-}
 
-fn open_db<P: AsRef<Path>>(
-    path: P,
-    rng: &'static Mutex<meta::GameRng>,
-) -> rusqlite::Result<rusqlite::Connection> {
-    let db = rusqlite::Connection::open(path)?;
+    /// Shows what went wrong instead of carrying on, after undoing any turn
+    /// that was left half done
+    fn fail(&mut self, err: anyhow::Error) {
+        // Whatever failed while showing an error is only logged, so retrying
+        // still goes back to where things first went wrong
+        if let ui::GameMode::Error(ref mut screen) = self.mode {
+            let causes: Vec<String> = err.chain().map(|cause| cause.to_string()).collect();
+            if screen.last_failure != causes {
+                screen.last_failure = causes;
+                log_error(&err);
+            }
+            return;
+        }
+        let mode = std::mem::replace(&mut self.mode, ui::GameMode::MainMenu(ui::main_menu()));
+        let mut screen = ui::ErrorScreen::new(&err, mode);
+        log_error(&err);
+        if let Some(Err(rollback_err)) = screen.previous.game().map(Game::rollback) {
+            let rollback_err = anyhow::Error::from(rollback_err).context("couldn't undo the turn");
+            log_error(&rollback_err);
+            screen
+                .causes
+                .extend(rollback_err.chain().map(|cause| cause.to_string()));
+        }
+        self.mode = ui::GameMode::Error(screen);
+        self.renderer.mark_dirty();
+    }
 
-    rusqlite::vtab::series::load_module(&db)?;
-    add_pcg_randint_function(&db, rng)?;
+    /// Moves on from the screen the player gave a command on, if the command
+    /// got anywhere
+    fn finish_step(&mut self, result: StepResult) -> anyhow::Result<()> {
+        // A finished game is saved, so it can't be loaded from before the end
+        match result {
+            StepResult::Ignored => return Ok(()),
+            StepResult::Continue => self.close_inventory(),
+            StepResult::Won => {
+                if let Some(game) = self.playing() {
+                    game.save()?;
+                }
+                self.mode = ui::GameMode::WonGame;
+            }
+            StepResult::Died(summary) => {
+                if let Some(game) = self.playing() {
+                    game.save()?;
+                    if let Some(path) = &game.path {
+                        summary.write_morgue(path)?;
+                    }
+                }
+                self.mode = ui::GameMode::Dead(summary);
+            }
+        }
+        self.renderer.mark_dirty();
+        Ok(())
+    }
 
-    db.execute_batch("PRAGMA foreign_keys = TRUE")?;
+    /// The game the player is in the middle of, if any
+    fn playing(&self) -> Option<&Game> {
+        match self.mode {
+            ui::GameMode::InGame { ref game, .. } | ui::GameMode::Inventory { ref game, .. } => {
+                Some(game)
+            }
+            _ => None,
+        }
+    }
 
-    Ok(db)
+    /// Takes the game out of the screen it's being played on, to move it to
+    /// another. The screen is left as it was if there's no game on it.
+    fn take_game(&mut self) -> Option<Game> {
+        match std::mem::replace(&mut self.mode, ui::GameMode::Transitioning) {
+            ui::GameMode::InGame { game, .. } | ui::GameMode::Inventory { game, .. } => Some(game),
+            mode => {
+                self.mode = mode;
+                None
+            }
+        }
+    }
+
+    fn close_inventory(&mut self) {
+        if let ui::GameMode::Inventory { .. } = self.mode {
+            if let Some(game) = self.take_game() {
+                self.mode = ui::GameMode::InGame {
+                    game,
+                    selected_point: None,
+                };
+            }
+        }
+        self.renderer.mark_dirty();
+    }
+}
+
+/// Opens the game in a slot to be played, or says why it couldn't be
+fn load(slot: &meta::SaveSlot) -> anyhow::Result<ui::GameMode> {
+    match Game::load(&slot.path, Storage::Memory) {
+        Ok((game, repairs)) => {
+            for repair in repairs {
+                log(&format!(
+                    "Repaired the save in slot '{}': {}",
+                    slot.name, repair
+                ));
+            }
+            Ok(ui::play(game)?)
+        }
+        Err(err) => Ok(ui::GameMode::LoadFailed(ui::LoadFailed::new(
+            ui::SlotPurpose::Load,
+            &slot.name,
+            &err,
+        ))),
+    }
+}
+
+/// Appends an error, with everything that caused it, to the error log
+fn log_error(err: &anyhow::Error) {
+    log(&format!("{:?}", err));
+}
+
+/// Appends an entry to the error log, or prints it if the log can't be
+/// written
+fn log(entry: &str) {
+    let logged = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(ERROR_LOG_FILE_NAME)
+        .and_then(|mut log| {
+            let time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            writeln!(log, "[{}] {}\n", time, entry)
+        });
+    if let Err(log_err) = logged {
+        eprintln!("Couldn't write to {}: {}", ERROR_LOG_FILE_NAME, log_err);
+        eprintln!("{}", entry);
+    }
 }
@@ -18,7 +18,7 @@ pub enum Tile {
 // }
 
 impl ToSql for Tile {
-    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput> {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
         match self {
             Tile::Unused => Ok(" ".into()),
             Tile::Floor => Ok(".".into()),
@@ -98,8 +98,8 @@ pub struct EmptyGenerator;
 impl Generator for EmptyGenerator {
     fn generate(&mut self, _: &mut meta::GameRng, width: i64, height: i64) -> Dungeon {
         let mut d = Dungeon {
-            width: width,
-            height: height,
+            width,
+            height,
             tiles: vec![Tile::Unused; (width * height) as usize],
         };
         for i in 0..d.width {
@@ -145,8 +145,8 @@ pub struct DefaultGenerator {
 impl Generator for DefaultGenerator {
     fn generate(&mut self, rng: &mut meta::GameRng, width: i64, height: i64) -> Dungeon {
         let mut dungeon = Dungeon {
-            width: width,
-            height: height,
+            width,
+            height,
             tiles: vec![Tile::Unused; (width * height) as usize],
         };
         let max_features = 50;
@@ -155,6 +155,12 @@ impl Generator for DefaultGenerator {
     }
 }
 
+impl Default for DefaultGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DefaultGenerator {
     pub fn new() -> Self {
         Self {
@@ -191,7 +197,6 @@ impl DefaultGenerator {
 
         if !self.place_object(rng, Tile::DownStairs, dungeon) {
             eprintln!("Unable to place down stairs.");
-            return;
         }
     }
 
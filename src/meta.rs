@@ -1,9 +1,7 @@
-use crate::console::{self, Console, ConsolePoint, VirtualKeyCode};
-use crate::profiler::TurnProfiler;
-use crate::{component, entity, game_object, system};
 use rand::SeedableRng;
-use std::collections::HashSet;
-use std::sync::{Arc, LazyLock};
+use std::path::{Path, PathBuf};
+
+use crate::{component, entity, game_object};
 
 pub type GameRng = rand_pcg::Pcg64Mcg;
 
@@ -11,216 +9,301 @@ pub fn init_rng() -> GameRng {
     GameRng::from_entropy()
 }
 
-pub const SAVE_FILE_NAME: &'static str = "game.db";
+/// Picks a seed for a game the player didn't choose one for
+pub fn new_seed() -> u64 {
+    rand::random()
+}
+
+pub fn seeded_rng(seed: u64) -> GameRng {
+    GameRng::seed_from_u64(seed)
+}
 
-pub const NEW_GAME: &str = "New Game";
-pub const LOAD_GAME: &str = "Load Game";
-pub const CREATIVE_MODE: &str = "Creative Mode";
+pub const SAVE_DIR: &str = "saves";
+pub const SAVE_EXTENSION: &str = "db";
+pub const MORGUE_EXTENSION: &str = "morgue.txt";
+pub const BACKUP_EXTENSION: &str = "bak";
+/// Where the one save lived before there were slots
+pub const LEGACY_SAVE_FILE_NAME: &str = "game.db";
+/// The slot an old save is moved into
+pub const LEGACY_SLOT_NAME: &str = "game";
 
-pub const CONSOLE_WIDTH: i64 = 80;
-pub const CONSOLE_HEIGHT: i64 = 30;
+/// Saves are backed up every this many turns
+pub const AUTOSAVE_INTERVAL: i64 = 50;
+/// How many backups of each save are kept
+pub const BACKUP_COUNT: usize = 2;
 
-pub const WORLD_TOP_LEFT: ConsolePoint = ConsolePoint { x: 0, y: 1 };
 pub const WORLD_WIDTH: i64 = 80;
 pub const WORLD_HEIGHT: i64 = 25;
 
-#[derive(Debug)]
-pub enum GameMode {
-    MainMenu(Menu),
-    InGame {
-        db: rusqlite::Connection,
-        player: entity::Entity,
-        profiler: TurnProfiler,
-        is_creative: bool,
-        selected_point: Option<ConsolePoint>,
-    },
-    WonGame,
-}
-
-pub fn in_game_keydown_handler(
-    db: &rusqlite::Connection,
-    keycodes: &HashSet<VirtualKeyCode>,
-    player: entity::Entity,
-) -> rusqlite::Result<Option<GameMode>> {
-    if component::player::outstanding_turns(db)? > 0 {
-        return Ok(None);
-    }
-    for keycode in keycodes {
-        match keycode {
-            VirtualKeyCode::Left => {
-                component::velocity::set(db, player, -1, 0)?;
-                component::player::schedule_time(db, 1)?;
-            }
-            VirtualKeyCode::Right => {
-                component::velocity::set(db, player, 1, 0)?;
-                component::player::schedule_time(db, 1)?;
-            }
-            VirtualKeyCode::Up => {
-                component::velocity::set(db, player, 0, -1)?;
-                component::player::schedule_time(db, 1)?;
-            }
-            VirtualKeyCode::Down => {
-                component::velocity::set(db, player, 0, 1)?;
-                component::player::schedule_time(db, 1)?;
-            }
-            VirtualKeyCode::Space | VirtualKeyCode::NumpadEnter => {
-                let new_level = system::follow_transition(db)?;
-                if new_level == Some(game_object::WIN_LEVEL.to_string()) {
-                    return Ok(Some(GameMode::WonGame));
-                }
-            }
+/// Reaching this floor wins the game
+pub const WIN_DEPTH: i64 = 5;
+
+/// How many of each item are scattered over every floor
+pub const GOLD_PER_LEVEL: i64 = 1;
+pub const HEALING_POTIONS_PER_LEVEL: i64 = 2;
+pub const TELEPORT_SCROLLS_PER_LEVEL: i64 = 1;
+pub const FIREBALL_SCROLLS_PER_LEVEL: i64 = 1;
+
+/// Where the save in the named slot lives
+pub fn slot_path(name: &str) -> PathBuf {
+    Path::new(SAVE_DIR).join(format!("{}.{}", name, SAVE_EXTENSION))
+}
+
+/// Where the `n`th newest backup of a save lives, counting from 0
+pub fn backup_path<P: AsRef<Path>>(save_path: P, n: usize) -> PathBuf {
+    save_path
+        .as_ref()
+        .with_extension(format!("{}.{}", n, BACKUP_EXTENSION))
+}
+
+/// Where SQLite keeps a save's write-ahead log and its index
+fn log_paths(save_path: &Path) -> Vec<PathBuf> {
+    ["-wal", "-shm"]
+        .iter()
+        .map(|suffix| {
+            let mut file = save_path.as_os_str().to_owned();
+            file.push(suffix);
+            file.into()
+        })
+        .collect()
+}
+
+fn remove_files(files: Vec<PathBuf>) -> std::io::Result<()> {
+    for file in files {
+        match std::fs::remove_file(file) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
             _ => {}
-        };
+        }
     }
-    Ok(None)
+    Ok(())
 }
 
-pub fn won_game_keydown_handler(keycode: &HashSet<VirtualKeyCode>, mode: &mut GameMode) {
-    if keycode.len() > 0 {
-        *mode = GameMode::MainMenu(main_menu())
-    }
+/// Removes a save along with its write-ahead log and backups. A stale log
+/// left behind would be replayed into the next save made at the same path.
+pub fn remove_save<P: AsRef<Path>>(save_path: P) -> std::io::Result<()> {
+    let save_path = save_path.as_ref();
+    let mut files = vec![save_path.to_path_buf()];
+    files.extend(log_paths(save_path));
+    files.extend((0..BACKUP_COUNT).map(|n| backup_path(save_path, n)));
+    remove_files(files)
 }
 
-#[derive(Debug, Default)]
-pub struct Renderer {
-    dirty: bool,
+/// Removes the backups made since the save was last written, which only a
+/// game kept in memory has. Done when the game is quit without saving, so
+/// they aren't offered on loading it.
+pub fn discard_unsaved_backups<P: AsRef<Path>>(save_path: P) -> std::io::Result<()> {
+    let save_path = save_path.as_ref();
+    let saved = saved_turn(save_path);
+    remove_files(
+        (0..BACKUP_COUNT)
+            .map(|n| backup_path(save_path, n))
+            .filter(|backup| saved_turn(backup) > saved)
+            .collect(),
+    )
 }
 
-impl Renderer {
-    pub fn new() -> Self {
-        Renderer { dirty: true }
-    }
+/// The turn a save or backup was made on, if it can be read
+fn saved_turn(path: &Path) -> Option<i64> {
+    let db =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .ok()?;
+    component::metadata::get(&db, component::metadata::TURN)
+        .ok()
+        .flatten()
+}
 
-    pub fn mark_dirty(&mut self) {
-        self.dirty = true;
-    }
+/// Copies the `n`th newest backup of a save over it. The save's log goes
+/// too, as it belongs to the save being replaced.
+pub fn restore_backup<P: AsRef<Path>>(save_path: P, n: usize) -> std::io::Result<()> {
+    let save_path = save_path.as_ref();
+    remove_files(log_paths(save_path))?;
+    std::fs::copy(backup_path(save_path, n), save_path)?;
+    Ok(())
+}
 
-    pub fn draw(
-        &mut self,
-        gamemode: &GameMode,
-        console: &mut Console,
-        ctx: &mut ggez::Context,
-    ) -> rusqlite::Result<()> {
-        if !self.dirty {
-            return Ok(());
-        }
-        console.cls(ctx);
-        match gamemode {
-            GameMode::MainMenu(menu) => Self::draw_menu(menu, console),
-            GameMode::InGame {
-                db, selected_point, ..
-            } => {
-                let visible_actors = component::actor::get_visible(db)?;
-                Self::draw_actors(&visible_actors, console);
-                let turn = component::player::turns_passed(db)?;
-                console.print(ConsolePoint { x: 0, y: 0 }, &turn.to_string());
-                if let Some(pos) = selected_point {
-                    console.print(
-                        ConsolePoint {
-                            x: 0,
-                            y: WORLD_HEIGHT + 2,
-                        },
-                        &format!("({:<2}, {:<2})", pos.x, pos.y),
-                    );
-                } else {
-                    console.print(
-                        ConsolePoint {
-                            x: 0,
-                            y: WORLD_HEIGHT + 2,
-                        },
-                        "Click something!",
-                    );
-                }
-            }
-            GameMode::WonGame => {
-                console.cls(ctx);
-                console.print(ConsolePoint { x: 1, y: 1 }, "You Win");
+/// A save in the saves directory, as described by its Meta table. Saves
+/// that can't be read are still listed, so they can be deleted.
+#[derive(Debug, Clone)]
+pub struct SaveSlot {
+    pub name: String,
+    pub path: PathBuf,
+    pub character: Option<String>,
+    pub depth: Option<i64>,
+    pub turn: Option<i64>,
+    pub last_played: Option<String>,
+    /// The turn of the newest backup, if it is further on than the save. A
+    /// game kept in memory is only backed up between saves, so this is
+    /// what is left of it after a crash.
+    pub backup_turn: Option<i64>,
+}
+
+impl SaveSlot {
+    /// Every save, the most recently played first
+    pub fn list() -> std::io::Result<Vec<SaveSlot>> {
+        import_legacy_save()?;
+        let entries = match std::fs::read_dir(SAVE_DIR) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let mut slots = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(SAVE_EXTENSION) {
+                continue;
             }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let mut slot = SaveSlot {
+                name: name.to_string(),
+                path: path.clone(),
+                character: None,
+                depth: None,
+                turn: None,
+                last_played: None,
+                backup_turn: None,
+            };
+            // Leaves the details blank if the save is too broken to read
+            let _ = slot.read_info();
+            slot.backup_turn =
+                saved_turn(&backup_path(&path, 0)).filter(|turn| Some(*turn) > slot.turn);
+            slots.push(slot);
         }
-        console.finish(ctx).expect("I'm dead!");
-        self.dirty = false;
+        slots.sort_by(|a, b| b.last_played.cmp(&a.last_played));
+        Ok(slots)
+    }
+
+    pub fn find(name: &str) -> std::io::Result<Option<SaveSlot>> {
+        Ok(SaveSlot::list()?.into_iter().find(|slot| slot.name == name))
+    }
+
+    fn read_info(&mut self) -> rusqlite::Result<()> {
+        let db = rusqlite::Connection::open_with_flags(
+            &self.path,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )?;
+        self.character = component::metadata::get(&db, component::metadata::CHARACTER)?;
+        self.depth = component::metadata::get(&db, component::metadata::DEPTH)?;
+        self.turn = component::metadata::get(&db, component::metadata::TURN)?;
+        self.last_played = component::metadata::get(&db, component::metadata::LAST_PLAYED)?;
         Ok(())
     }
 
-    fn draw_actors(actors: &Vec<component::actor::Actor>, console: &mut Console) {
-        for actor in actors {
-            console.print_color(
-                actor.pos.into(),
-                actor.color,
-                game_object::BACKGROUND_COLOR,
-                &actor.tile,
-            );
+    pub fn description(&self) -> String {
+        match (&self.character, self.depth, self.turn, &self.last_played) {
+            (Some(character), Some(depth), Some(turn), Some(last_played)) => format!(
+                "{}: {}, depth {}, turn {}, last played {}",
+                self.name, character, depth, turn, last_played
+            ),
+            _ => format!("{}: unreadable save", self.name),
         }
     }
 
-    fn draw_menu(menu: &Menu, console: &mut Console) {
-        for (i, item) in menu.items.iter().enumerate() {
-            let color: game_object::MenuColor;
-            if i == menu.selected {
-                color = game_object::MENU_COLOR_SELECTED;
-            } else {
-                color = game_object::MENU_COLOR_UNSELECTED;
-            }
-            console.print_color(menu.top_left.down(i as i64), color.fg, color.bg, item)
+    /// Removes the save, its backups and its morgue file
+    pub fn delete(&self) -> std::io::Result<()> {
+        remove_save(&self.path)?;
+        match std::fs::remove_file(self.path.with_extension(MORGUE_EXTENSION)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
         }
     }
 }
 
+/// Moves the save from before there were slots into a slot of its own,
+/// unless that slot is already taken
+fn import_legacy_save() -> std::io::Result<()> {
+    let legacy = Path::new(LEGACY_SAVE_FILE_NAME);
+    let slot = slot_path(LEGACY_SLOT_NAME);
+    if !legacy.exists() || slot.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(SAVE_DIR)?;
+    std::fs::rename(legacy, &slot)?;
+    match std::fs::rename(
+        legacy.with_extension(MORGUE_EXTENSION),
+        slot.with_extension(MORGUE_EXTENSION),
+    ) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+/// What happened during a finished run, for the game over screen and the
+/// morgue file.
 #[derive(Debug, Clone)]
-pub struct Menu {
-    top_left: console::ConsolePoint,
-    selected: usize,
-    items: Arc<Vec<String>>,
-}
-
-pub enum MenuResult<'a> {
-    None,
-    Updated,
-    Selected(&'a str),
-    Back,
-}
-
-pub fn keydown_handler<'a>(
-    keycodes: &HashSet<VirtualKeyCode>,
-    menu: &'a mut Menu,
-) -> MenuResult<'a> {
-    for keycode in keycodes {
-        match keycode {
-            VirtualKeyCode::Left | VirtualKeyCode::Up => {
-                menu.add(-1);
-                return MenuResult::Updated;
-            }
-            VirtualKeyCode::Right | VirtualKeyCode::Down => {
-                menu.add(1);
-                return MenuResult::Updated;
-            }
-            VirtualKeyCode::Space | VirtualKeyCode::NumpadEnter | VirtualKeyCode::Return => {
-                return MenuResult::Selected(&menu.items[menu.selected]);
-            }
-            VirtualKeyCode::Escape => return MenuResult::Back,
-            _ => {}
+pub struct RunSummary {
+    pub cause_of_death: String,
+    pub turns: i64,
+    pub depth: i64,
+    pub kills: i64,
+    pub seed: Option<u64>,
+}
+
+impl RunSummary {
+    pub fn load(db: &rusqlite::Connection) -> rusqlite::Result<Self> {
+        Ok(RunSummary {
+            cause_of_death: component::player::cause_of_death(db)?
+                .unwrap_or_else(|| "unknown causes".to_string()),
+            turns: component::player::turns_passed(db)?,
+            depth: component::player::depth(db)?,
+            kills: component::player::kills(db)?,
+            seed: component::metadata::seed(db)?,
+        })
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![
+            format!("Killed by {} on depth {}.", self.cause_of_death, self.depth),
+            format!("Survived for {} turns.", self.turns),
+            format!("Killed {} monsters.", self.kills),
+        ];
+        if let Some(seed) = self.seed {
+            lines.push(format!("Seed {}.", seed));
         }
+        lines
     }
-    MenuResult::None
-}
-
-pub fn main_menu() -> Menu {
-    static MAIN_MENU_ITEMS: LazyLock<Arc<Vec<String>>> = LazyLock::new(|| {
-        Arc::new(vec![
-            CREATIVE_MODE.to_string(),
-            NEW_GAME.to_string(),
-            LOAD_GAME.to_string(),
-        ])
-    });
-    Menu {
-        top_left: ConsolePoint { x: 0, y: 0 },
-        selected: 0,
-        items: MAIN_MENU_ITEMS.clone(),
+
+    /// Writes the summary as a plain text file next to the save file.
+    pub fn write_morgue<P: AsRef<Path>>(&self, save_path: P) -> std::io::Result<()> {
+        let mut contents = format!("{} morgue file\n\n", game_object::PLAYER_NAME);
+        for line in self.lines() {
+            contents.push_str(&line);
+            contents.push('\n');
+        }
+        std::fs::write(
+            save_path.as_ref().with_extension(MORGUE_EXTENSION),
+            contents,
+        )
     }
 }
 
-impl Menu {
-    pub fn add(&mut self, i: i64) {
-        self.selected = (self.selected as i64 + i).rem_euclid(self.items.len() as i64) as usize
+/// Everything the player can do that takes time. These are what get
+/// recorded for replays, so they must not depend on anything but the game.
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    Move { dx: i64, dy: i64 },
+    Wait,
+    OpenDoor,
+    CloseDoor,
+    FollowTransition,
+    PickUp,
+    Drop(entity::Entity),
+    Use(entity::Entity),
+}
+
+impl Command {
+    /// How much of the player's energy the command uses up. Fiddling with
+    /// items is quicker than getting about.
+    pub fn cost(&self) -> i64 {
+        match self {
+            Command::PickUp | Command::Drop(_) => component::energy::ACTION_COST / 2,
+            Command::Move { .. }
+            | Command::Wait
+            | Command::OpenDoor
+            | Command::CloseDoor
+            | Command::FollowTransition
+            | Command::Use(_) => component::energy::ACTION_COST,
+        }
     }
 }
@@ -0,0 +1,135 @@
+use bracket_pathfinding::prelude::{
+    Algorithm2D, BaseMap, DijkstraMap, DistanceAlg, Point, SmallVec,
+};
+
+use crate::game_object::WorldPoint;
+use crate::meta;
+
+const DIAGONAL_COST: f32 = 1.45;
+const MAX_PATH_LENGTH: f32 = 1000.0;
+
+/// Which tiles of the active level can be walked on, in the shape
+/// bracket-pathfinding expects.
+pub struct PassableMap {
+    passable: Vec<bool>,
+}
+
+impl PassableMap {
+    pub fn load(db: &rusqlite::Connection) -> rusqlite::Result<Self> {
+        let mut map = PassableMap {
+            passable: vec![false; (meta::WORLD_WIDTH * meta::WORLD_HEIGHT) as usize],
+        };
+        let mut query = db.prepare_cached("SELECT x, y FROM PassableTiles")?;
+        let tiles = query.query_map((), |row| {
+            Ok(WorldPoint {
+                x: row.get(0)?,
+                y: row.get(1)?,
+            })
+        })?;
+        for pos in tiles {
+            if let Some(idx) = map.index(pos?) {
+                map.passable[idx] = true;
+            }
+        }
+        Ok(map)
+    }
+
+    /// Works out the shortest path from every tile to `target`, so any number
+    /// of monsters can chase it for the cost of a single search.
+    pub fn paths_to(&self, target: WorldPoint) -> Option<PathsTo<'_>> {
+        let target = self.index(target)?;
+        let mut distances = DijkstraMap::new(
+            meta::WORLD_WIDTH,
+            meta::WORLD_HEIGHT,
+            &[target],
+            self,
+            MAX_PATH_LENGTH,
+        );
+        // The search only fills in the target's neighbours, so without this
+        // it looks further away than them.
+        distances.map[target] = 0.0;
+        Some(PathsTo {
+            map: self,
+            distances,
+            target,
+        })
+    }
+
+    fn index(&self, pos: WorldPoint) -> Option<usize> {
+        let point = Point::new(pos.x, pos.y);
+        if self.in_bounds(point) {
+            Some(self.point2d_to_index(point))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct PathsTo<'a> {
+    map: &'a PassableMap,
+    distances: DijkstraMap,
+    target: usize,
+}
+
+impl PathsTo<'_> {
+    /// The first tile along the shortest path from `from` to the target, or
+    /// `None` if the target can't be reached.
+    pub fn next_step(&self, from: WorldPoint) -> Option<WorldPoint> {
+        let idx = self.map.index(from)?;
+        // Whoever is at `from` stands on it, so it usually isn't passable and
+        // has no distance of its own. Their neighbours do, as does the
+        // target, which is stepped into to attack it.
+        let offset = self.map.index_to_point2d(self.target) - self.map.index_to_point2d(idx);
+        let is_next_to_target = offset.x.abs() <= 1 && offset.y.abs() <= 1;
+        let step = self
+            .map
+            .get_available_exits(idx)
+            .into_iter()
+            .map(|(exit, _)| exit)
+            .chain(is_next_to_target.then_some(self.target))
+            .min_by(|a, b| self.distances.map[*a].total_cmp(&self.distances.map[*b]))?;
+        if self.distances.map[step] >= self.distances.map[idx].min(MAX_PATH_LENGTH) {
+            return None;
+        }
+        let step = self.map.index_to_point2d(step);
+        Some(WorldPoint {
+            x: step.x as i64,
+            y: step.y as i64,
+        })
+    }
+}
+
+impl BaseMap for PassableMap {
+    fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
+        let mut exits = SmallVec::new();
+        let location = self.index_to_point2d(idx);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let exit = location + Point::new(dx, dy);
+                if (dx == 0 && dy == 0) || !self.in_bounds(exit) {
+                    continue;
+                }
+                let exit_idx = self.point2d_to_index(exit);
+                if self.passable[exit_idx] {
+                    let cost = if dx != 0 && dy != 0 {
+                        DIAGONAL_COST
+                    } else {
+                        1.0
+                    };
+                    exits.push((exit_idx, cost));
+                }
+            }
+        }
+        exits
+    }
+
+    fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
+        DistanceAlg::Pythagoras.distance2d(self.index_to_point2d(idx1), self.index_to_point2d(idx2))
+    }
+}
+
+impl Algorithm2D for PassableMap {
+    fn dimensions(&self) -> Point {
+        Point::new(meta::WORLD_WIDTH, meta::WORLD_HEIGHT)
+    }
+}
@@ -1,22 +1,11 @@
 use rusqlite::named_params;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct TurnProfiler {}
 
 impl TurnProfiler {
-    pub fn new(db: &rusqlite::Connection) -> rusqlite::Result<Self> {
-        db.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS TurnSplit (
-                turn INTEGER NOT NULL,
-                split TEXT,
-                ms INTEGER
-            );
-            CREATE UNIQUE INDEX IF NOT EXISTS idx_turnsplit_turn_split ON TurnSplit (turn ASC, split);
-            CREATE INDEX IF NOT EXISTS idx_turnsplit_split ON TurnSplit (split);
-        ",
-        )?;
-        Ok(TurnProfiler {})
+    pub fn new() -> Self {
+        TurnProfiler {}
     }
 
     pub fn start(&mut self) -> TurnStart {
@@ -52,6 +41,43 @@ impl TurnProfiler {
         )?;
         Ok(())
     }
+
+    /// Records work done for a turn after it finished, such as backing it up
+    pub fn record(
+        &mut self,
+        db: &rusqlite::Connection,
+        turn: i64,
+        split: &str,
+        duration: std::time::Duration,
+    ) -> rusqlite::Result<()> {
+        db.execute(
+            "INSERT INTO TurnSplit (turn, split, ms)
+            VALUES (:turn, :split, :ms)
+            ON CONFLICT (turn, split) DO UPDATE SET ms = excluded.ms",
+            named_params! {":turn": turn, ":split": split, ":ms": duration.as_millis() as u64},
+        )?;
+        Ok(())
+    }
+}
+
+/// How long one part of a turn took
+#[derive(Debug, Clone)]
+pub struct Split {
+    pub turn: i64,
+    pub split: String,
+    pub ms: i64,
+}
+
+pub fn load_splits(db: &rusqlite::Connection) -> rusqlite::Result<Vec<Split>> {
+    let mut stmt = db.prepare("SELECT turn, split, ms FROM TurnSplit ORDER BY turn, rowid")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(Split {
+            turn: row.get(0)?,
+            split: row.get(1)?,
+            ms: row.get(2)?,
+        })
+    })?;
+    rows.collect()
 }
 
 pub struct TurnStart {
@@ -0,0 +1,580 @@
+//! The save schema, and how older saves are brought up to date with it.
+//!
+//! A save's `PRAGMA user_version` is the number of migrations it has had
+//! applied. New games start at 0 and run them all, so there is only one way
+//! the schema gets built. Saves from before versioning are also at 0, in
+//! whatever shape the build that wrote them left them, so every migration
+//! has to cope with the change it makes already being there.
+//!
+//! Each step spells out its own SQL rather than sharing it with the rest of
+//! the game, so what a step does never changes once it has shipped.
+
+use anyhow::Context;
+
+type Migration = fn(&rusqlite::Connection) -> rusqlite::Result<()>;
+
+/// Every change to the schema, oldest first. Only ever add to the end.
+const MIGRATIONS: &[(&str, Migration)] = &[
+    ("core tables", core_tables),
+    ("actors on levels", actor_levels),
+    ("combat", combat),
+    ("death records", death_records),
+    ("field of view", vision),
+    ("doors", doors),
+    ("items", items),
+    ("effects", effects),
+    ("equipment", equipment),
+    ("save metadata", metadata),
+    ("replays", replays),
+    ("energy", energy),
+    ("passable tiles under monsters", passable_tiles),
+];
+
+/// The newest schema this build understands
+pub const SCHEMA_VERSION: i64 = MIGRATIONS.len() as i64;
+
+pub fn version(db: &rusqlite::Connection) -> rusqlite::Result<i64> {
+    db.query_row("PRAGMA user_version", (), |row| row.get(0))
+}
+
+/// Applies whatever migrations the save hasn't had yet, each in its own
+/// transaction. Refuses saves written by a newer build rather than guessing
+/// at what they contain.
+pub fn migrate(db: &rusqlite::Connection) -> anyhow::Result<()> {
+    let current = version(db)?;
+    if current > SCHEMA_VERSION {
+        anyhow::bail!(
+            "this save is from a newer version of the game (save format {}, but this build only \
+            understands up to {})",
+            current,
+            SCHEMA_VERSION
+        );
+    }
+    for (version, (name, migration)) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        db.execute_batch("BEGIN TRANSACTION")?;
+        migration(db)
+            .and_then(|_| db.pragma_update(None, "user_version", version as i64 + 1))
+            .and_then(|_| db.execute_batch("COMMIT TRANSACTION"))
+            .or_else(|err| db.execute_batch("ROLLBACK TRANSACTION").and(Err(err)))
+            .with_context(|| format!("couldn't migrate the save to {}", name))?;
+    }
+    Ok(())
+}
+
+fn has_column(db: &rusqlite::Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    db.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?",
+        [table, column],
+        |row| row.get(0),
+    )
+}
+
+fn add_column(
+    db: &rusqlite::Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    if !has_column(db, table, column)? {
+        db.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+/// Entity, Player, Actor, Velocity, Collision, Health, Ai, Transition and
+/// TurnSplit, as every save has had them from the start
+fn core_tables(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Entity (id INTEGER PRIMARY KEY);
+
+        CREATE TABLE IF NOT EXISTS Player (
+            entity INTEGER UNIQUE NOT NULL,
+            is_creative BOOLEAN,
+            turn INTEGER,
+            outstanding_turns INTEGER,
+            level TEXT,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS Actor (
+            entity INTEGER NOT NULL,
+            tile TEXT,
+            x INTEGER,
+            y INTEGER,
+            r INTEGER,
+            g INTEGER,
+            B INTEGER,
+            plane INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_actor_entity ON Actor (entity ASC);
+        CREATE INDEX IF NOT EXISTS idx_actor_plane ON Actor (plane DESC);
+
+        CREATE TABLE IF NOT EXISTS Velocity (
+            entity INTEGER NOT NULL,
+            dx INTEGER,
+            dy INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_velocity_entity ON Velocity (entity ASC);
+
+        CREATE TABLE IF NOT EXISTS Collision (
+            entity INTEGER UNIQUE NOT NULL,
+            ground BOOLEAN,
+            solid BOOLEAN,
+            ephemeral BOOLEAN,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS Health (
+            entity INTEGER UNIQUE NOT NULL,
+            max INTEGER,
+            current INTEGER,
+            regen INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS Ai (
+            entity INTEGER UNIQUE NOT NULL,
+            type TEXT,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS Transition (
+            entity INTEGER UNIQUE NOT NULL,
+            level TEXT,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS TurnSplit (
+            turn INTEGER NOT NULL,
+            split TEXT,
+            ms INTEGER
+        );
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_turnsplit_turn_split ON TurnSplit (turn ASC, split);
+        CREATE INDEX IF NOT EXISTS idx_turnsplit_split ON TurnSplit (split);
+        ",
+    )
+}
+
+/// Actors used to all be on the one level the player was on
+fn actor_levels(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(db, "Actor", "level", "TEXT")?;
+    db.execute_batch(
+        "
+        UPDATE Actor SET level = (SELECT level FROM Player) WHERE level IS NULL;
+        DROP INDEX IF EXISTS idx_actor_position;
+        CREATE INDEX idx_actor_position ON Actor (level, x ASC, y ASC);
+
+        DROP VIEW IF EXISTS PassableTiles;
+        CREATE VIEW PassableTiles AS
+        SELECT Collision.entity, Actor.x, Actor.y
+        FROM Collision
+        JOIN Actor ON Actor.entity = Collision.entity
+        WHERE Collision.ground = 1
+        AND Actor.level = (SELECT level FROM Player)
+        AND Collision.entity NOT IN (
+            SELECT Collision.entity
+            FROM Collision
+            JOIN Actor ON Actor.entity = Collision.entity
+            JOIN Actor ground_actor
+                ON ground_actor.level = Actor.level
+                AND ground_actor.x = Actor.x
+                AND ground_actor.y = Actor.y
+            JOIN Collision ground_collision ON ground_collision.entity = ground_actor.entity
+            WHERE Collision.solid = 1 AND ground_collision.ground = 1
+        );
+        ",
+    )
+}
+
+fn combat(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Attack (
+            entity INTEGER UNIQUE NOT NULL,
+            power INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        CREATE TABLE IF NOT EXISTS Defense (
+            entity INTEGER UNIQUE NOT NULL,
+            armor INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        ",
+    )
+}
+
+fn death_records(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    add_column(db, "Player", "kills", "INTEGER")?;
+    add_column(db, "Player", "cause_of_death", "TEXT")?;
+    // Not a foreign key, the attacker may be long gone
+    add_column(db, "Health", "last_damaged_by", "INTEGER")?;
+    db.execute_batch(
+        "
+        UPDATE Player SET kills = 0 WHERE kills IS NULL;
+        CREATE TABLE IF NOT EXISTS Name (
+            entity INTEGER UNIQUE NOT NULL,
+            name TEXT,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        ",
+    )
+}
+
+fn vision(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Visible (
+            x INTEGER,
+            y INTEGER,
+            UNIQUE (x, y)
+        );
+        CREATE TABLE IF NOT EXISTS Remembered (
+            level TEXT,
+            x INTEGER,
+            y INTEGER,
+            tile TEXT,
+            UNIQUE (level, x, y)
+        );
+        ",
+    )
+}
+
+fn doors(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Door (
+            entity INTEGER UNIQUE NOT NULL,
+            open BOOLEAN,
+            -- Set when someone wants the door opened or closed this turn
+            toggle BOOLEAN,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        ",
+    )
+}
+
+fn items(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        -- How an item looks while it is lying on the floor
+        CREATE TABLE IF NOT EXISTS Item (
+            entity INTEGER UNIQUE NOT NULL,
+            tile TEXT,
+            r INTEGER,
+            g INTEGER,
+            b INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        CREATE TABLE IF NOT EXISTS Inventory (
+            owner INTEGER NOT NULL,
+            item INTEGER UNIQUE NOT NULL,
+            FOREIGN KEY (owner) REFERENCES Entity (id) ON DELETE CASCADE,
+            FOREIGN KEY (item) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_inventory_owner ON Inventory (owner);
+        ",
+    )
+}
+
+/// Effects are SQL run against the existing components. They can use the
+/// :user, :magnitude and :radius parameters.
+fn effects(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS EffectKind (
+            name TEXT PRIMARY KEY,
+            query TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS Effect (
+            entity INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            magnitude INTEGER,
+            radius INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE,
+            FOREIGN KEY (kind) REFERENCES EffectKind (name)
+        );
+        CREATE INDEX IF NOT EXISTS idx_effect_entity ON Effect (entity);
+        ",
+    )?;
+    let mut insert = db.prepare(
+        "INSERT INTO EffectKind (name, query)
+        VALUES (?, ?)
+        ON CONFLICT (name) DO UPDATE SET query = excluded.query",
+    )?;
+    insert.execute([
+        "heal",
+        "UPDATE Health
+        SET current = min(
+            (SELECT max_health FROM Stats WHERE entity = :user),
+            current + :magnitude
+        )
+        WHERE entity = :user",
+    ])?;
+    insert.execute([
+        "teleport",
+        "UPDATE Actor
+        SET (x, y) = (
+            SELECT x, y
+            FROM Actor
+            WHERE Actor.entity IN (SELECT entity FROM PassableTiles)
+            ORDER BY pcg_random()
+            LIMIT 1
+        )
+        WHERE entity = :user",
+    ])?;
+    insert.execute([
+        "area_damage",
+        "UPDATE Health
+        SET current = current - :magnitude, last_damaged_by = :user
+        WHERE entity IN (
+            SELECT target.entity
+            FROM Actor target
+            JOIN Actor user ON user.level = target.level
+            WHERE user.entity = :user
+            AND target.entity != :user
+            AND (target.x - user.x) * (target.x - user.x)
+                + (target.y - user.y) * (target.y - user.y) <= :radius * :radius
+        )",
+    ])?;
+    Ok(())
+}
+
+/// Adds the Stats view, the combat stats of every entity once its equipment
+/// is taken into account
+fn equipment(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Equipment (
+            entity INTEGER UNIQUE NOT NULL,
+            slot TEXT NOT NULL,
+            attack_bonus INTEGER,
+            defense_bonus INTEGER,
+            max_health_bonus INTEGER,
+            regen_bonus INTEGER,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        CREATE TABLE IF NOT EXISTS Equipped (
+            owner INTEGER NOT NULL,
+            item INTEGER UNIQUE NOT NULL,
+            slot TEXT NOT NULL,
+            UNIQUE (owner, slot),
+            FOREIGN KEY (owner) REFERENCES Entity (id) ON DELETE CASCADE,
+            FOREIGN KEY (item) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+
+        CREATE VIEW IF NOT EXISTS Stats AS
+        SELECT
+            Entity.id AS entity,
+            COALESCE(Attack.power, 0) + COALESCE(Bonus.attack, 0) AS attack,
+            COALESCE(Defense.armor, 0) + COALESCE(Bonus.defense, 0) AS defense,
+            Health.max + COALESCE(Bonus.max_health, 0) AS max_health,
+            Health.regen + COALESCE(Bonus.regen, 0) AS regen
+        FROM Entity
+        LEFT JOIN Attack ON Attack.entity = Entity.id
+        LEFT JOIN Defense ON Defense.entity = Entity.id
+        LEFT JOIN Health ON Health.entity = Entity.id
+        LEFT JOIN (
+            SELECT
+                Equipped.owner,
+                SUM(Equipment.attack_bonus) AS attack,
+                SUM(Equipment.defense_bonus) AS defense,
+                SUM(Equipment.max_health_bonus) AS max_health,
+                SUM(Equipment.regen_bonus) AS regen
+            FROM Equipped
+            JOIN Equipment ON Equipment.entity = Equipped.item
+            GROUP BY Equipped.owner
+        ) AS Bonus ON Bonus.owner = Entity.id;
+        ",
+    )
+}
+
+fn metadata(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Meta (
+            key TEXT PRIMARY KEY,
+            value
+        );
+        ",
+    )
+}
+
+fn replays(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Replay (
+            id INTEGER PRIMARY KEY,
+            turn INTEGER NOT NULL,
+            command TEXT NOT NULL,
+            dx INTEGER,
+            dy INTEGER,
+            item INTEGER
+        );
+        ",
+    )
+}
+
+/// Turns used to be counted out on the player. Everything that moves now
+/// needs energy to, so older actors start out at normal speed, and the
+/// player with enough energy to act straight away.
+fn energy(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS Energy (
+            entity INTEGER UNIQUE NOT NULL,
+            speed INTEGER NOT NULL,
+            energy INTEGER NOT NULL,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        -- The actors taking their action in the current phase of a turn
+        CREATE TABLE IF NOT EXISTS Acting (
+            entity INTEGER UNIQUE NOT NULL,
+            FOREIGN KEY (entity) REFERENCES Entity (id) ON DELETE CASCADE
+        );
+        ",
+    )?;
+    if has_column(db, "Player", "outstanding_turns")? {
+        db.execute_batch("ALTER TABLE Player DROP COLUMN outstanding_turns")?;
+    }
+    db.execute_batch(
+        "
+        INSERT INTO Energy (entity, speed, energy)
+        SELECT Velocity.entity, 100, CASE
+            WHEN Velocity.entity IN (SELECT entity FROM Player) THEN 100
+            ELSE 0
+        END
+        FROM Velocity
+        WHERE Velocity.entity NOT IN (SELECT entity FROM Energy);
+        ",
+    )
+}
+
+/// Ground under a monster used to still count as empty, so monsters could
+/// be spawned on top of each other
+fn passable_tiles(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        DROP VIEW IF EXISTS PassableTiles;
+        CREATE VIEW PassableTiles AS
+        SELECT Collision.entity, Actor.x, Actor.y
+        FROM Collision
+        JOIN Actor ON Actor.entity = Collision.entity
+        WHERE Collision.ground = 1
+        AND Actor.level = (SELECT level FROM Player)
+        AND Collision.entity NOT IN (
+            SELECT ground_actor.entity
+            FROM Collision
+            JOIN Actor ON Actor.entity = Collision.entity
+            JOIN Actor ground_actor
+                ON ground_actor.level = Actor.level
+                AND ground_actor.x = Actor.x
+                AND ground_actor.y = Actor.y
+            JOIN Collision ground_collision ON ground_collision.entity = ground_actor.entity
+            WHERE Collision.solid = 1 AND ground_collision.ground = 1
+        );
+        ",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A save as the builds from before versioning left it: the core
+    /// tables, with turns still counted out on the player and no levels on
+    /// the actors
+    fn legacy_db() -> rusqlite::Connection {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        core_tables(&db).unwrap();
+        db.execute_batch(
+            "
+            INSERT INTO Entity (id) VALUES (1), (2);
+            INSERT INTO Player (entity, is_creative, turn, outstanding_turns, level)
+            VALUES (1, FALSE, 12, 0, '0');
+            INSERT INTO Actor (entity, tile, x, y, r, g, b, plane)
+            VALUES (1, '@', 3, 4, 255, 255, 255, 2), (2, '.', 3, 4, 128, 128, 128, 0);
+            INSERT INTO Velocity (entity, dx, dy) VALUES (1, 0, 0);
+            INSERT INTO Health (entity, max, current, regen) VALUES (1, 30, 30, 0);
+            ",
+        )
+        .unwrap();
+        db
+    }
+
+    /// Every table, index and view, and how many rows each table has
+    fn snapshot(db: &rusqlite::Connection) -> Vec<(String, String, i64)> {
+        let objects = db
+            .prepare("SELECT type, name FROM sqlite_master ORDER BY name")
+            .unwrap()
+            .query_map((), |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(String, String)>>>()
+            .unwrap();
+        objects
+            .into_iter()
+            .map(|(kind, name)| {
+                let rows = if kind == "table" {
+                    db.query_row(&format!("SELECT COUNT(*) FROM {}", name), (), |row| {
+                        row.get(0)
+                    })
+                    .unwrap()
+                } else {
+                    0
+                };
+                (kind, name, rows)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn legacy_saves_are_brought_up_to_date() {
+        let db = legacy_db();
+        migrate(&db).unwrap();
+        assert_eq!(version(&db).unwrap(), SCHEMA_VERSION);
+        assert!(!has_column(&db, "Player", "outstanding_turns").unwrap());
+        let (level, kills): (String, i64) = db
+            .query_row(
+                "SELECT Actor.level, Player.kills
+                FROM Actor
+                JOIN Player ON Player.entity = Actor.entity",
+                (),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((level.as_str(), kills), ("0", 0));
+        let energy: i64 = db
+            .query_row("SELECT energy FROM Energy WHERE entity = 1", (), |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(energy > 0);
+    }
+
+    #[test]
+    fn newer_saves_are_refused() {
+        let db = rusqlite::Connection::open_in_memory().unwrap();
+        db.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        let err = migrate(&db).unwrap_err().to_string();
+        assert!(err.contains("newer version of the game"), "{}", err);
+        assert_eq!(version(&db).unwrap(), SCHEMA_VERSION + 1);
+    }
+
+    #[test]
+    fn migrating_twice_changes_nothing() {
+        let db = legacy_db();
+        migrate(&db).unwrap();
+        let before = snapshot(&db);
+        let changes = db.total_changes();
+        migrate(&db).unwrap();
+        assert_eq!(snapshot(&db), before);
+        assert_eq!(db.total_changes(), changes);
+        assert_eq!(version(&db).unwrap(), SCHEMA_VERSION);
+    }
+}
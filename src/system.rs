@@ -1,26 +1,161 @@
 use rusqlite::{named_params, OptionalExtension};
 
-use crate::component;
+use crate::{component, entity, fov, game_object, meta, navigation};
 
 pub fn move_actors(db: &rusqlite::Connection) -> rusqlite::Result<()> {
     db.execute_batch(
         "
-        -- Move the actor according to its velocity
-        UPDATE Actor
-        SET x = Actor.x + Velocity.dx, y = Actor.y + Velocity.dy
-        FROM Velocity
-        WHERE Velocity.entity = Actor.entity
-        -- as long as it is not an actor that would move to a tile with solid colision
-        AND Actor.entity NOT IN (
-            SELECT Collision.entity
-            FROM Collision
-            JOIN Actor ON Actor.entity = Collision.entity
-            JOIN Velocity ON Actor.entity = Velocity.entity
-            LEFT JOIN Actor solid_actor ON solid_actor.x = Actor.x + Velocity.dx AND solid_actor.y = Actor.y + Velocity.dy
+        -- Solid actors that would move into a solid actor with health attack
+        -- it instead. They are still blocked by it below.
+        UPDATE Health
+        SET current = current - damage.amount, last_damaged_by = damage.attacker
+        FROM (
+            SELECT target_actor.entity AS target, max(Attack.entity) AS attacker, SUM(max(
+                pcg_randint(1, attacker_stats.attack) - pcg_randint(0, target_stats.defense),
+                0
+            )) AS amount
+            FROM Attack
+            JOIN Stats attacker_stats ON attacker_stats.entity = Attack.entity
+            JOIN Actor ON Actor.entity = Attack.entity
+            JOIN Velocity ON Velocity.entity = Attack.entity
+            JOIN Collision ON Collision.entity = Attack.entity
+            JOIN Actor target_actor
+                ON target_actor.level = Actor.level
+                AND target_actor.x = Actor.x + Velocity.dx
+                AND target_actor.y = Actor.y + Velocity.dy
+            JOIN Collision target_collision ON target_collision.entity = target_actor.entity
+            JOIN Stats target_stats ON target_stats.entity = target_actor.entity
+            WHERE Attack.entity IN (SELECT entity FROM Acting)
+            AND (Velocity.dx != 0 OR Velocity.dy != 0)
+            AND Collision.solid AND target_collision.solid
+            -- Monsters don't fight each other
+            AND (
+                Actor.entity IN (SELECT entity FROM Player)
+                OR target_actor.entity IN (SELECT entity FROM Player)
+            )
+            GROUP BY target_actor.entity
+        ) AS damage
+        WHERE Health.entity = damage.target;
+
+        -- Move everything acting according to its velocity, unless it is a
+        -- solid actor that is blocked or loses its destination to another
+        WITH move AS (
+            SELECT
+                Actor.entity,
+                Actor.level,
+                Actor.x + Velocity.dx AS x,
+                Actor.y + Velocity.dy AS y,
+                COALESCE(Collision.solid, FALSE) AS solid,
+                Actor.entity IN (SELECT entity FROM Player) AS is_player
+            FROM Actor
+            JOIN Velocity ON Velocity.entity = Actor.entity
+            LEFT JOIN Collision ON Collision.entity = Actor.entity
+            WHERE Actor.entity IN (SELECT entity FROM Acting)
+            AND (Velocity.dx != 0 OR Velocity.dy != 0)
+        ),
+        blocked AS (
+            -- Solid actors can't move into anything solid, even if it is
+            -- moving out of the way itself, so nothing can swap places
+            SELECT move.entity
+            FROM move
+            JOIN Actor solid_actor
+                ON solid_actor.level = move.level
+                AND solid_actor.x = move.x
+                AND solid_actor.y = move.y
             JOIN Collision solid_collision ON solid_collision.entity = solid_actor.entity
-            WHERE Collision.solid AND solid_collision.solid
+            WHERE move.solid AND solid_collision.solid
+            UNION
+            -- Solid actors heading for the same tile claim it in priority
+            -- order: the player first, then whoever has been around longest.
+            -- The rest stay where they are.
+            SELECT move.entity
+            FROM move
+            JOIN move rival
+                ON rival.level = move.level
+                AND rival.x = move.x
+                AND rival.y = move.y
+                AND rival.entity != move.entity
+            WHERE move.solid AND rival.solid
+            AND (
+                rival.is_player > move.is_player
+                OR (rival.is_player = move.is_player AND rival.entity < move.entity)
+            )
         )
-        ")
+        UPDATE Actor
+        SET x = move.x, y = move.y
+        FROM move
+        WHERE move.entity = Actor.entity
+        AND move.entity NOT IN (SELECT entity FROM blocked);
+        ",
+    )
+}
+
+pub fn operate_doors(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    db.execute_batch(
+        "
+        -- Solid actors that walk into a closed door open it, which takes the
+        -- place of their move
+        UPDATE Door
+        SET toggle = TRUE
+        WHERE NOT open AND entity IN (
+            SELECT door_actor.entity
+            FROM Actor
+            JOIN Velocity ON Velocity.entity = Actor.entity
+            JOIN Collision ON Collision.entity = Actor.entity
+            JOIN Actor door_actor
+                ON door_actor.level = Actor.level
+                AND door_actor.x = Actor.x + Velocity.dx
+                AND door_actor.y = Actor.y + Velocity.dy
+            WHERE Actor.entity IN (SELECT entity FROM Acting)
+            AND (Velocity.dx != 0 OR Velocity.dy != 0)
+            AND Collision.solid
+        );
+        UPDATE Velocity
+        SET dx = 0, dy = 0
+        FROM Actor, Actor door_actor, Door
+        WHERE Actor.entity = Velocity.entity
+        AND Velocity.entity IN (SELECT entity FROM Acting)
+        AND door_actor.level = Actor.level
+        AND door_actor.x = Actor.x + Velocity.dx
+        AND door_actor.y = Actor.y + Velocity.dy
+        AND Door.entity = door_actor.entity
+        AND NOT Door.open AND Door.toggle;
+
+        -- Doors can't be closed on anything standing in the doorway, though
+        -- they shut straight through passing particles
+        UPDATE Door
+        SET toggle = FALSE
+        WHERE open AND toggle AND entity IN (
+            SELECT door_actor.entity
+            FROM Actor door_actor
+            JOIN Actor ON Actor.level = door_actor.level
+                AND Actor.x = door_actor.x
+                AND Actor.y = door_actor.y
+            JOIN Collision ON Collision.entity = Actor.entity
+            WHERE Actor.entity != door_actor.entity
+            AND NOT Collision.ground
+            AND NOT Collision.ephemeral
+        );
+
+        UPDATE Door SET open = NOT open WHERE toggle;
+        UPDATE Collision
+        SET solid = NOT Door.open
+        FROM Door
+        WHERE Door.entity = Collision.entity AND Door.toggle;
+        ",
+    )?;
+    db.execute(
+        "UPDATE Actor
+        SET tile = CASE WHEN Door.open THEN :open_tile ELSE :closed_tile END
+        FROM Door
+        WHERE Door.entity = Actor.entity AND Door.toggle",
+        named_params! {
+            ":open_tile": component::door::OPEN_TILE,
+            ":closed_tile": component::door::CLOSED_TILE,
+        },
+    )?;
+    db.execute("UPDATE Door SET toggle = FALSE WHERE toggle", ())?;
+    Ok(())
 }
 
 pub fn follow_transition(db: &rusqlite::Connection) -> rusqlite::Result<Option<String>> {
@@ -31,7 +166,8 @@ pub fn follow_transition(db: &rusqlite::Connection) -> rusqlite::Result<Option<S
         FROM Transition
         JOIN Actor transition_actor ON transition_actor.entity = Transition.entity
         JOIN Actor player_actor
-            ON player_actor.x = transition_actor.x
+            ON player_actor.level = transition_actor.level
+            AND player_actor.x = transition_actor.x
             AND player_actor.y = transition_actor.y
         WHERE player_actor.entity = Player.entity
         RETURNING level
@@ -45,11 +181,11 @@ pub fn follow_transition(db: &rusqlite::Connection) -> rusqlite::Result<Option<S
 pub fn apply_ai(db: &rusqlite::Connection) -> rusqlite::Result<()> {
     db.execute(
         "
-        -- This query randomly updates all velocities with a random AI Type to
-        -- one of the 8 cardinal directions. The spurious-seeming cor.val field
-        -- forces the RANDINT call in a correlated subquery, which forces it to
-        -- be executed once per row. Otherwise every particle moves in the same
-        -- direction
+        -- This query randomly updates the velocities of everything acting with
+        -- a random AI Type to one of the 8 cardinal directions. The
+        -- spurious-seeming cor.val field forces the RANDINT call in a
+        -- correlated subquery, which forces it to be executed once per row.
+        -- Otherwise every particle moves in the same direction
         UPDATE Velocity
         SET (dx, dy) = (
             SELECT
@@ -67,26 +203,102 @@ pub fn apply_ai(db: &rusqlite::Connection) -> rusqlite::Result<()> {
         )
         FROM Ai, (SELECT 1 AS val) AS cor
         WHERE Ai.entity = Velocity.entity
-            AND Ai.type = :type",
+            AND Ai.type = :type
+            AND Ai.entity IN (SELECT entity FROM Acting)",
         named_params! {":type": component::ai::AI_TYPE_RANDOM},
     )?;
 
+    // Chasers head one step along the shortest path to the player, and
+    // wander randomly if they can't reach them.
+    let chasers = component::ai::get_acting(db, component::ai::AI_TYPE_CHASE)?;
+    if chasers.is_empty() {
+        return Ok(());
+    }
+    let player = entity::load_player(db)?;
+    let target = component::actor::get_position(db, player)?;
+    let map = navigation::PassableMap::load(db)?;
+    let paths = target.and_then(|target| map.paths_to(target));
+    for (entity, pos) in chasers {
+        match paths.as_ref().and_then(|paths| paths.next_step(pos)) {
+            Some(step) => component::velocity::set(db, entity, step.x - pos.x, step.y - pos.y)?,
+            None => component::velocity::set_random(db, entity, -1..=1)?,
+        }
+    }
+
     Ok(())
 }
 
+/// Works out what the player can see from where they are now
+pub fn update_fov(db: &rusqlite::Connection) -> rusqlite::Result<()> {
+    let player = entity::load_player(db)?;
+    let Some(origin) = component::actor::get_position(db, player)? else {
+        return Ok(());
+    };
+    let opaque = component::vision::get_opaque(db)?;
+    let in_bounds = |pos: &game_object::WorldPoint| {
+        pos.x >= 0 && pos.y >= 0 && pos.x < meta::WORLD_WIDTH && pos.y < meta::WORLD_HEIGHT
+    };
+    let mut visible = fov::compute(origin, |pos| !in_bounds(&pos) || opaque.contains(&pos));
+    visible.retain(in_bounds);
+    component::vision::set_visible(db, &visible)
+}
+
 pub fn apply_regen(db: &rusqlite::Connection) -> rusqlite::Result<()> {
-    db.execute_batch("UPDATE Health SET current = current + regen")?;
+    db.execute_batch(
+        "UPDATE Health
+        SET
+            current = min(current + Stats.regen, Stats.max_health),
+            -- Wasting away is nobody's doing, so nobody gets the kill
+            last_damaged_by = CASE
+                WHEN current > 0 AND current + Stats.regen <= 0 THEN NULL
+                ELSE last_damaged_by
+            END
+        FROM Stats
+        WHERE Stats.entity = Health.entity
+        AND Health.entity IN (SELECT entity FROM Actor WHERE level = (SELECT level FROM Player))",
+    )?;
     Ok(())
 }
 
 pub fn cull_dead(db: &rusqlite::Connection) -> rusqlite::Result<()> {
     db.execute_batch(
-        "DELETE FROM Entity
+        "
+        -- Credit the player with everything they finished off
+        UPDATE Player
+        SET kills = kills + (
+            SELECT COUNT(*)
+            FROM Health
+            JOIN Actor ON Actor.entity = Health.entity
+            WHERE Health.current <= 0
+            AND Health.last_damaged_by = Player.entity
+            AND Actor.level = Player.level
+            AND Health.entity != Player.entity
+        );
+
+        -- Remember who killed the player before they get culled themselves
+        UPDATE Player
+        SET cause_of_death = COALESCE(
+            (
+                SELECT 'a ' || Name.name
+                FROM Health
+                JOIN Name ON Name.entity = Health.last_damaged_by
+                WHERE Health.entity = Player.entity
+            ),
+            'unknown causes'
+        )
+        WHERE cause_of_death IS NULL
+        AND (SELECT current FROM Health WHERE Health.entity = Player.entity) <= 0;
+
+        DELETE FROM Entity
         WHERE id IN (
             SELECT id
             FROM Entity
             JOIN Health ON Entity.id = Health.entity
+            JOIN Actor ON Entity.id = Actor.entity
             WHERE Health.current <= 0
+            AND Actor.level = (SELECT level FROM Player)
+            -- The player's death ends the game instead
+            AND Entity.id NOT IN (SELECT entity FROM Player)
         )",
     )?;
     Ok(())
@@ -99,13 +311,20 @@ pub fn cull_ephemeral(db: &rusqlite::Connection) -> rusqlite::Result<()> {
             SELECT Collision.entity
             FROM Collision
             JOIN Actor ON Actor.entity = Collision.entity
-            JOIN Actor solid_actor ON solid_actor.x = Actor.x AND solid_actor.y = Actor.y
+            JOIN Actor solid_actor
+                ON solid_actor.level = Actor.level
+                AND solid_actor.x = Actor.x
+                AND solid_actor.y = Actor.y
             JOIN Collision solid_collision ON solid_collision.entity = solid_actor.entity
-            WHERE Collision.ephemeral AND (solid_collision.solid OR Collision.entity NOT IN (
+            WHERE Actor.level = (SELECT level FROM Player)
+            AND Collision.ephemeral AND (solid_collision.solid OR Collision.entity NOT IN (
                 SELECT Collision.entity
                 FROM Collision
                 JOIN Actor ON Actor.entity = Collision.entity
-                JOIN Actor ground_actor ON ground_actor.x = Actor.x AND ground_actor.y = Actor.y
+                JOIN Actor ground_actor
+                    ON ground_actor.level = Actor.level
+                    AND ground_actor.x = Actor.x
+                    AND ground_actor.y = Actor.y
                 JOIN Collision ground_collision ON ground_collision.entity = ground_actor.entity
                 WHERE Collision.ephemeral AND ground_collision.ground
             ))
@@ -113,3 +332,195 @@ pub fn cull_ephemeral(db: &rusqlite::Connection) -> rusqlite::Result<()> {
     )?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_object::WorldPoint;
+    use crate::test_fixtures::{init_player, new_db, place, position, spawn};
+
+    /// Sets everyone's velocity, makes them all act and moves them
+    fn move_all(db: &rusqlite::Connection, moves: &[(entity::Entity, i64, i64)]) {
+        component::energy::clear_acting(db).unwrap();
+        for &(entity, dx, dy) in moves {
+            component::velocity::set(db, entity, dx, dy).unwrap();
+            db.execute("INSERT INTO Acting (entity) VALUES (?)", [entity])
+                .unwrap();
+        }
+        move_actors(db).unwrap();
+    }
+
+    fn shared_solid_tiles(db: &rusqlite::Connection) -> i64 {
+        db.query_row(
+            "SELECT COUNT(*) FROM (
+                SELECT 1
+                FROM Actor
+                JOIN Collision ON Collision.entity = Actor.entity
+                WHERE Collision.solid
+                GROUP BY level, x, y
+                HAVING COUNT(*) > 1
+            )",
+            (),
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn only_one_solid_claims_a_tile() {
+        let db = new_db();
+        init_player(&db, 10, 10);
+        let first = spawn(&db, 1, 1, true);
+        let second = spawn(&db, 3, 1, true);
+        move_all(&db, &[(first, 1, 0), (second, -1, 0)]);
+        assert_eq!(position(&db, first), (2, 1));
+        assert_eq!(position(&db, second), (3, 1));
+        assert_eq!(shared_solid_tiles(&db), 0);
+    }
+
+    #[test]
+    fn player_claims_first() {
+        let db = new_db();
+        // Created before the player, so it would win on age alone
+        let monster = entity::create(&db).unwrap();
+        let player = init_player(&db, 3, 1);
+        place(&db, monster, 1, 1, true);
+        move_all(&db, &[(monster, 1, 0), (player, -1, 0)]);
+        assert_eq!(position(&db, player), (2, 1));
+        assert_eq!(position(&db, monster), (1, 1));
+    }
+
+    #[test]
+    fn solids_cannot_swap() {
+        let db = new_db();
+        init_player(&db, 10, 10);
+        let left = spawn(&db, 1, 1, true);
+        let right = spawn(&db, 2, 1, true);
+        move_all(&db, &[(left, 1, 0), (right, -1, 0)]);
+        assert_eq!(position(&db, left), (1, 1));
+        assert_eq!(position(&db, right), (2, 1));
+    }
+
+    #[test]
+    fn solids_cannot_move_onto_stationary_solids() {
+        let db = new_db();
+        init_player(&db, 10, 10);
+        let wall = spawn(&db, 2, 1, true);
+        let mover = spawn(&db, 1, 1, true);
+        move_all(&db, &[(mover, 1, 0)]);
+        assert_eq!(position(&db, mover), (1, 1));
+        assert_eq!(position(&db, wall), (2, 1));
+    }
+
+    #[test]
+    fn non_solids_move_freely() {
+        let db = new_db();
+        init_player(&db, 10, 10);
+        let solid = spawn(&db, 1, 1, true);
+        let particle = spawn(&db, 3, 1, false);
+        move_all(&db, &[(solid, 1, 0), (particle, -1, 0)]);
+        assert_eq!(position(&db, solid), (2, 1));
+        assert_eq!(position(&db, particle), (2, 1));
+    }
+
+    #[test]
+    fn only_acting_actors_move() {
+        let db = new_db();
+        init_player(&db, 10, 10);
+        let idle = spawn(&db, 1, 1, true);
+        let mover = spawn(&db, 5, 5, true);
+        component::velocity::set(&db, idle, 1, 0).unwrap();
+        move_all(&db, &[(mover, 0, 1)]);
+        assert_eq!(position(&db, idle), (1, 1));
+        assert_eq!(position(&db, mover), (5, 6));
+    }
+
+    #[test]
+    fn crowds_never_share_tiles() {
+        let db = new_db();
+        init_player(&db, 0, 0);
+        let crowd = (0..5)
+            .flat_map(|x| (0..5).map(move |y| (x * 2, y * 2)))
+            .map(|(x, y)| spawn(&db, x + 1, y + 1, true))
+            .collect::<Vec<_>>();
+        for _ in 0..50 {
+            component::energy::clear_acting(&db).unwrap();
+            for &entity in &crowd {
+                component::velocity::set_random(&db, entity, -1..=1).unwrap();
+                db.execute("INSERT INTO Acting (entity) VALUES (?)", [entity])
+                    .unwrap();
+            }
+            move_actors(&db).unwrap();
+            assert_eq!(shared_solid_tiles(&db), 0);
+        }
+    }
+
+    #[test]
+    fn chasers_head_straight_for_the_player() {
+        let db = new_db();
+        for x in 0..12 {
+            for y in 0..3 {
+                game_object::init_floor(&db, WorldPoint { x, y }, "0").unwrap();
+            }
+        }
+        let player = init_player(&db, 10, 1);
+        let chaser = spawn(&db, 1, 1, true);
+        component::ai::set_chase(&db, chaser).unwrap();
+        // Every step is along the row, the last one into the player
+        for x in 1..10 {
+            component::energy::clear_acting(&db).unwrap();
+            db.execute("INSERT INTO Acting (entity) VALUES (?)", [chaser])
+                .unwrap();
+            apply_ai(&db).unwrap();
+            let velocity: (i64, i64) = db
+                .query_row(
+                    "SELECT dx, dy FROM Velocity WHERE entity = ?",
+                    [chaser],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+            assert_eq!(velocity, (1, 0), "from ({}, 1)", x);
+            move_actors(&db).unwrap();
+        }
+        assert_eq!(position(&db, chaser), (9, 1));
+        assert_eq!(position(&db, player), (10, 1));
+    }
+
+    /// Leaves `entity` on `current` health, last hurt by `attacker`
+    fn wound(
+        db: &rusqlite::Connection,
+        entity: entity::Entity,
+        current: i64,
+        attacker: entity::Entity,
+    ) {
+        component::health::set(db, entity, 10, current, -1).unwrap();
+        db.execute(
+            "UPDATE Health SET last_damaged_by = ? WHERE entity = ?",
+            (attacker, entity),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn finishing_off_is_a_kill() {
+        let db = new_db();
+        let player = init_player(&db, 10, 10);
+        let monster = spawn(&db, 1, 1, true);
+        wound(&db, monster, 0, player);
+        apply_regen(&db).unwrap();
+        cull_dead(&db).unwrap();
+        assert_eq!(component::player::kills(&db).unwrap(), 1);
+    }
+
+    #[test]
+    fn wasting_away_is_not_a_kill() {
+        let db = new_db();
+        let player = init_player(&db, 10, 10);
+        let monster = spawn(&db, 1, 1, true);
+        wound(&db, monster, 1, player);
+        apply_regen(&db).unwrap();
+        cull_dead(&db).unwrap();
+        assert_eq!(component::actor::get_position(&db, monster).unwrap(), None);
+        assert_eq!(component::player::kills(&db).unwrap(), 0);
+    }
+}
@@ -0,0 +1,47 @@
+//! Databases and actors shared by the tests
+
+use std::sync::{Arc, Mutex};
+
+use crate::game_object::{self, Plane, WorldPoint};
+use crate::{component, entity, game, meta, schema};
+
+/// An empty, up to date game database
+pub fn new_db() -> rusqlite::Connection {
+    let rng = Arc::new(Mutex::new(meta::seeded_rng(0)));
+    let db = game::open_db(":memory:", rng).unwrap();
+    schema::migrate(&db).unwrap();
+    db
+}
+
+pub fn spawn(db: &rusqlite::Connection, x: i64, y: i64, solid: bool) -> entity::Entity {
+    let entity = entity::create(db).unwrap();
+    place(db, entity, x, y, solid);
+    entity
+}
+
+pub fn place(db: &rusqlite::Connection, entity: entity::Entity, x: i64, y: i64, solid: bool) {
+    component::actor::set(
+        db,
+        component::actor::Actor {
+            entity,
+            tile: "x".into(),
+            pos: WorldPoint { x, y },
+            color: game_object::ENEMY_COLOR,
+            plane: Plane::Enemies,
+            level: "0".into(),
+        },
+    )
+    .unwrap();
+    component::collision::set(db, entity, false, solid, false).unwrap();
+}
+
+pub fn init_player(db: &rusqlite::Connection, x: i64, y: i64) -> entity::Entity {
+    let player = game_object::init_player(db, false).unwrap();
+    game_object::init_player_actor(db, player, WorldPoint { x, y }, "0").unwrap();
+    player
+}
+
+pub fn position(db: &rusqlite::Connection, entity: entity::Entity) -> (i64, i64) {
+    let pos = component::actor::get_position(db, entity).unwrap().unwrap();
+    (pos.x, pos.y)
+}
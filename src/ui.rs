@@ -0,0 +1,1031 @@
+use crate::console::{self, Console, ConsolePoint, VirtualKeyCode};
+use crate::keymap::{self, Action, Keymap};
+use rust_roguelike::meta::{Command, RunSummary, SaveSlot, WORLD_HEIGHT};
+use rust_roguelike::{component, entity, game_object, map_gen, Game, Recording, Storage};
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::{Arc, LazyLock};
+
+pub const NEW_GAME: &str = "New Game";
+pub const NEW_SEEDED_GAME: &str = "New Game With Seed";
+pub const LOAD_GAME: &str = "Load Game";
+pub const CREATIVE_MODE: &str = "Creative Mode";
+pub const WATCH_REPLAY: &str = "Watch Replay";
+pub const OPTIONS: &str = "Options";
+
+pub const NEW_SLOT: &str = "New Slot";
+pub const NO_SAVES: &str = "No saved games";
+
+pub const RESET_KEYS: &str = "Reset to Defaults";
+pub const SAVE_KEYS: &str = "Save";
+
+pub const RETRY: &str = "Retry";
+pub const MAIN_MENU: &str = "Main Menu";
+
+pub const CONSOLE_WIDTH: i64 = 80;
+pub const CONSOLE_HEIGHT: i64 = 30;
+
+pub const WORLD_TOP_LEFT: ConsolePoint = ConsolePoint { x: 0, y: 1 };
+
+pub const REPLAY_TURNS_PER_SECOND: u32 = 10;
+pub const MAX_REPLAY_TURNS_PER_SECOND: u32 = 60;
+
+#[derive(Debug)]
+pub enum GameMode {
+    MainMenu(Menu),
+    SeedEntry(String),
+    SlotSelect(SlotSelect),
+    SlotNameEntry {
+        new_game: NewGame,
+        input: String,
+    },
+    Confirm(Confirm),
+    LoadFailed(LoadFailed),
+    Error(ErrorScreen),
+    InGame {
+        game: Game,
+        selected_point: Option<ConsolePoint>,
+    },
+    Inventory {
+        game: Game,
+        menu: Menu,
+        items: Vec<entity::Entity>,
+    },
+    Replay(Replay),
+    Options(Options),
+    WonGame,
+    Dead(RunSummary),
+    /// Only held while a game is moved from one screen to the next, and
+    /// never left in place when that fails
+    Transitioning,
+}
+
+impl GameMode {
+    /// The game being played or watched on this screen, if there is one
+    pub fn game(&self) -> Option<&Game> {
+        match self {
+            GameMode::InGame { game, .. } | GameMode::Inventory { game, .. } => Some(game),
+            GameMode::Replay(replay) => Some(&replay.game),
+            GameMode::Error(screen) => screen.previous.game(),
+            _ => None,
+        }
+    }
+}
+
+/// A game being rebuilt from its seed by playing back a save's commands
+#[derive(Debug)]
+pub struct Replay {
+    pub game: Game,
+    pub commands: Recording,
+    pub turns_per_second: u32,
+    pub ticks_waited: u32,
+    pub finished: bool,
+    /// The first recorded turn the replay no longer lined up with
+    pub diverged_at: Option<i64>,
+}
+
+impl Replay {
+    pub fn new(game: Game, commands: Recording) -> Self {
+        Replay {
+            game,
+            commands,
+            turns_per_second: REPLAY_TURNS_PER_SECOND,
+            ticks_waited: 0,
+            finished: false,
+            diverged_at: None,
+        }
+    }
+}
+
+/// How to start a game once it has a slot to go in
+#[derive(Debug, Clone, Copy)]
+pub struct NewGame {
+    pub is_creative: bool,
+    pub seed: u64,
+}
+
+/// What a save slot is being picked for
+#[derive(Debug, Clone, Copy)]
+pub enum SlotPurpose {
+    NewGame(NewGame),
+    Load,
+    Replay,
+}
+
+#[derive(Debug)]
+pub struct SlotSelect {
+    pub purpose: SlotPurpose,
+    pub menu: Menu,
+    pub slots: Vec<SaveSlot>,
+}
+
+impl SlotSelect {
+    pub fn new(purpose: SlotPurpose) -> std::io::Result<Self> {
+        let slots = SaveSlot::list()?;
+        let mut items = slots.iter().map(SaveSlot::description).collect::<Vec<_>>();
+        match purpose {
+            SlotPurpose::NewGame(_) => items.insert(0, NEW_SLOT.to_string()),
+            _ if items.is_empty() => items.push(NO_SAVES.to_string()),
+            _ => {}
+        }
+        Ok(SlotSelect {
+            purpose,
+            menu: Menu::new(ConsolePoint { x: 1, y: 2 }, items),
+            slots,
+        })
+    }
+
+    fn selected_slot(&self) -> Option<&SaveSlot> {
+        match self.purpose {
+            SlotPurpose::NewGame(_) => self.menu.selected.checked_sub(1),
+            _ => Some(self.menu.selected),
+        }
+        .and_then(|i| self.slots.get(i))
+    }
+}
+
+/// A save that couldn't be opened, and everything that went wrong opening it
+#[derive(Debug)]
+pub struct LoadFailed {
+    pub purpose: SlotPurpose,
+    pub name: String,
+    pub causes: Vec<String>,
+}
+
+impl LoadFailed {
+    pub fn new(purpose: SlotPurpose, name: &str, err: &anyhow::Error) -> Self {
+        LoadFailed {
+            purpose,
+            name: name.to_string(),
+            causes: err.chain().map(|cause| cause.to_string()).collect(),
+        }
+    }
+}
+
+/// Something went wrong partway through a screen. The screen is kept so
+/// it can be tried again once the game has been rolled back.
+#[derive(Debug)]
+pub struct ErrorScreen {
+    pub causes: Vec<String>,
+    pub menu: Menu,
+    pub previous: Box<GameMode>,
+    /// What last went wrong while this screen was up, so an error that
+    /// happens every frame is only logged once
+    pub last_failure: Vec<String>,
+}
+
+impl ErrorScreen {
+    pub fn new(err: &anyhow::Error, previous: GameMode) -> Self {
+        let causes: Vec<String> = err.chain().map(|cause| cause.to_string()).collect();
+        ErrorScreen {
+            last_failure: causes.clone(),
+            causes,
+            menu: Menu::new(
+                ConsolePoint { x: 1, y: 2 },
+                vec![RETRY.to_string(), MAIN_MENU.to_string()],
+            ),
+            previous: Box::new(previous),
+        }
+    }
+}
+
+pub enum ErrorResult {
+    None,
+    Updated,
+    Retry,
+    MainMenu,
+}
+
+pub fn error_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    screen: &mut ErrorScreen,
+    keymap: &Keymap,
+) -> ErrorResult {
+    match keydown_handler(keycodes, &mut screen.menu, keymap) {
+        MenuResult::None => ErrorResult::None,
+        MenuResult::Updated => ErrorResult::Updated,
+        MenuResult::Selected(RETRY) => ErrorResult::Retry,
+        MenuResult::Selected(_) | MenuResult::Back => ErrorResult::MainMenu,
+    }
+}
+
+pub enum SlotResult {
+    None,
+    Updated,
+    NewSlot,
+    Selected(SaveSlot),
+    Delete(SaveSlot),
+    Back,
+}
+
+pub enum NameEntryResult {
+    None,
+    Updated,
+    Done(String),
+    Back,
+}
+
+/// A question that has to be answered with y or n before anything is lost
+#[derive(Debug)]
+pub struct Confirm {
+    pub prompt: String,
+    pub action: ConfirmAction,
+}
+
+#[derive(Debug)]
+pub enum ConfirmAction {
+    Overwrite {
+        slot: SaveSlot,
+        new_game: NewGame,
+    },
+    Delete {
+        slot: SaveSlot,
+        purpose: SlotPurpose,
+    },
+    Abandon {
+        game: Game,
+    },
+    /// Loads the slot's newest backup in place of the save if yes, or the
+    /// save as it is if no
+    Restore {
+        slot: SaveSlot,
+    },
+}
+
+impl Confirm {
+    pub fn overwrite(slot: SaveSlot, new_game: NewGame) -> Self {
+        Confirm {
+            prompt: format!("Overwrite {}?", slot.description()),
+            action: ConfirmAction::Overwrite { slot, new_game },
+        }
+    }
+
+    pub fn delete(slot: SaveSlot, purpose: SlotPurpose) -> Self {
+        Confirm {
+            prompt: format!("Delete {}?", slot.description()),
+            action: ConfirmAction::Delete { slot, purpose },
+        }
+    }
+
+    pub fn restore(slot: SaveSlot) -> Self {
+        Confirm {
+            prompt: format!(
+                "Load {} from its newer backup, made on turn {}?",
+                slot.name,
+                slot.backup_turn.unwrap_or_default()
+            ),
+            action: ConfirmAction::Restore { slot },
+        }
+    }
+
+    pub fn abandon(game: Game) -> Self {
+        Confirm {
+            prompt: "Quit without saving? Everything since the last save will be lost.".to_string(),
+            action: ConfirmAction::Abandon { game },
+        }
+    }
+}
+
+pub enum ConfirmResult {
+    None,
+    Yes,
+    No,
+}
+
+/// The key bindings being edited, before they are saved
+#[derive(Debug)]
+pub struct Options {
+    pub keymap: Keymap,
+    pub menu: Menu,
+    /// Whether the next key pressed gets bound to the selected action
+    pub rebinding: bool,
+    /// Why the last change or save was refused
+    pub message: Option<String>,
+}
+
+impl Options {
+    pub fn new(keymap: Keymap) -> Self {
+        let mut options = Options {
+            keymap,
+            menu: Menu::new(ConsolePoint { x: 1, y: 2 }, vec![]),
+            rebinding: false,
+            message: None,
+        };
+        options.refresh_menu();
+        options
+    }
+
+    /// The action on the selected line, if it isn't one of the buttons
+    fn selected_action(&self) -> Option<Action> {
+        keymap::ACTIONS
+            .get(self.menu.selected)
+            .map(|(action, ..)| *action)
+    }
+
+    fn refresh_menu(&mut self) {
+        let mut items = keymap::ACTIONS
+            .iter()
+            .map(|(action, ..)| {
+                let keys = self
+                    .keymap
+                    .keys(*action)
+                    .iter()
+                    .map(keymap::key_name)
+                    .collect::<Vec<_>>();
+                format!("{:<16} {}", action.label(), keys.join(", "))
+            })
+            .collect::<Vec<_>>();
+        items.push(RESET_KEYS.to_string());
+        items.push(SAVE_KEYS.to_string());
+        self.menu.items = Arc::new(items);
+    }
+}
+
+pub enum OptionsResult {
+    None,
+    Updated,
+    Save,
+    Back,
+}
+
+pub enum InGameResult {
+    None,
+    Updated,
+    Command(Command),
+    OpenInventory,
+    CloseInventory,
+    Save,
+    Quit,
+    QuitWithoutSaving,
+}
+
+/// Starts a new game in the save at the given path, saving it straight away
+/// so the slot is taken
+pub fn start(path: &Path, new_game: NewGame) -> anyhow::Result<GameMode> {
+    let game = if new_game.is_creative {
+        Game::new(
+            path,
+            Storage::Memory,
+            true,
+            new_game.seed,
+            map_gen::EmptyGenerator,
+        )?
+    } else {
+        Game::new(
+            path,
+            Storage::Memory,
+            false,
+            new_game.seed,
+            map_gen::DefaultGenerator::new(),
+        )?
+    };
+    game.save()?;
+    Ok(play(game)?)
+}
+
+/// Starts playing a game, or goes straight to how it ended if it's already
+/// over
+pub fn play(game: Game) -> rusqlite::Result<GameMode> {
+    if game.is_dead()? {
+        return Ok(GameMode::Dead(game.summary()?));
+    }
+    if game.has_won()? {
+        return Ok(GameMode::WonGame);
+    }
+    Ok(GameMode::InGame {
+        game,
+        selected_point: None,
+    })
+}
+
+pub fn in_game_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    keymap: &Keymap,
+) -> InGameResult {
+    let Some(action) = keymap.action(keycodes, keymap::GAME_ACTIONS) else {
+        return InGameResult::None;
+    };
+    let command = match action {
+        Action::MoveNorth => Command::Move { dx: 0, dy: -1 },
+        Action::MoveSouth => Command::Move { dx: 0, dy: 1 },
+        Action::MoveWest => Command::Move { dx: -1, dy: 0 },
+        Action::MoveEast => Command::Move { dx: 1, dy: 0 },
+        Action::MoveNorthWest => Command::Move { dx: -1, dy: -1 },
+        Action::MoveNorthEast => Command::Move { dx: 1, dy: -1 },
+        Action::MoveSouthWest => Command::Move { dx: -1, dy: 1 },
+        Action::MoveSouthEast => Command::Move { dx: 1, dy: 1 },
+        Action::Wait => Command::Wait,
+        Action::OpenDoor => Command::OpenDoor,
+        Action::CloseDoor => Command::CloseDoor,
+        Action::UseStairs => Command::FollowTransition,
+        Action::PickUp => Command::PickUp,
+        Action::Inventory => return InGameResult::OpenInventory,
+        Action::Save => return InGameResult::Save,
+        Action::Quit => return InGameResult::Quit,
+        Action::QuitWithoutSaving => return InGameResult::QuitWithoutSaving,
+        Action::MenuUp
+        | Action::MenuDown
+        | Action::MenuSelect
+        | Action::MenuBack
+        | Action::DropItem => return InGameResult::None,
+    };
+    InGameResult::Command(command)
+}
+
+/// The menu of what the player is carrying, and the item behind each entry
+pub fn inventory_menu(game: &Game) -> rusqlite::Result<(Menu, Vec<entity::Entity>)> {
+    let (items, mut names): (Vec<entity::Entity>, Vec<String>) =
+        component::inventory::get(&game.db, game.player)?
+            .into_iter()
+            .unzip();
+    if names.is_empty() {
+        names.push("Your pack is empty".to_string());
+    }
+    Ok((Menu::new(ConsolePoint { x: 1, y: 2 }, names), items))
+}
+
+pub fn inventory_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    menu: &mut Menu,
+    items: &[entity::Entity],
+    keymap: &Keymap,
+) -> InGameResult {
+    if keymap.action(keycodes, &[Action::DropItem]).is_some() {
+        if let Some(&item) = items.get(menu.selected) {
+            return InGameResult::Command(Command::Drop(item));
+        }
+    }
+    match keydown_handler(keycodes, menu, keymap) {
+        MenuResult::Selected(_) => match items.get(menu.selected) {
+            Some(&item) => InGameResult::Command(Command::Use(item)),
+            None => InGameResult::None,
+        },
+        MenuResult::Back => InGameResult::CloseInventory,
+        MenuResult::Updated => InGameResult::Updated,
+        _ => InGameResult::None,
+    }
+}
+
+/// Longest seed that always fits in a u64
+const MAX_SEED_DIGITS: usize = 19;
+const MAX_SLOT_NAME_LENGTH: usize = 20;
+
+pub enum SeedEntryResult {
+    None,
+    Updated,
+    Start(u64),
+    Back,
+}
+
+pub fn seed_entry_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    input: &mut String,
+) -> SeedEntryResult {
+    for keycode in keycodes {
+        let digit = match keycode {
+            VirtualKeyCode::Key0 | VirtualKeyCode::Numpad0 => '0',
+            VirtualKeyCode::Key1 | VirtualKeyCode::Numpad1 => '1',
+            VirtualKeyCode::Key2 | VirtualKeyCode::Numpad2 => '2',
+            VirtualKeyCode::Key3 | VirtualKeyCode::Numpad3 => '3',
+            VirtualKeyCode::Key4 | VirtualKeyCode::Numpad4 => '4',
+            VirtualKeyCode::Key5 | VirtualKeyCode::Numpad5 => '5',
+            VirtualKeyCode::Key6 | VirtualKeyCode::Numpad6 => '6',
+            VirtualKeyCode::Key7 | VirtualKeyCode::Numpad7 => '7',
+            VirtualKeyCode::Key8 | VirtualKeyCode::Numpad8 => '8',
+            VirtualKeyCode::Key9 | VirtualKeyCode::Numpad9 => '9',
+            VirtualKeyCode::Back => {
+                input.pop();
+                return SeedEntryResult::Updated;
+            }
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                return match input.parse() {
+                    Ok(seed) => SeedEntryResult::Start(seed),
+                    Err(_) => SeedEntryResult::None,
+                };
+            }
+            VirtualKeyCode::Escape => return SeedEntryResult::Back,
+            _ => continue,
+        };
+        if input.len() < MAX_SEED_DIGITS {
+            input.push(digit);
+            return SeedEntryResult::Updated;
+        }
+    }
+    SeedEntryResult::None
+}
+
+pub enum ReplayResult {
+    None,
+    Updated,
+    Back,
+}
+
+pub fn replay_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    replay: &mut Replay,
+) -> ReplayResult {
+    for keycode in keycodes {
+        match keycode {
+            VirtualKeyCode::Equals | VirtualKeyCode::NumpadAdd => {
+                replay.turns_per_second =
+                    (replay.turns_per_second + 1).min(MAX_REPLAY_TURNS_PER_SECOND);
+                return ReplayResult::Updated;
+            }
+            VirtualKeyCode::Minus | VirtualKeyCode::NumpadSubtract => {
+                replay.turns_per_second = (replay.turns_per_second - 1).max(1);
+                return ReplayResult::Updated;
+            }
+            VirtualKeyCode::Escape => return ReplayResult::Back,
+            _ => {}
+        }
+    }
+    ReplayResult::None
+}
+
+pub fn slot_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    select: &mut SlotSelect,
+    keymap: &Keymap,
+) -> SlotResult {
+    if keycodes.contains(&VirtualKeyCode::Delete) || keycodes.contains(&VirtualKeyCode::Back) {
+        return match select.selected_slot() {
+            Some(slot) => SlotResult::Delete(slot.clone()),
+            None => SlotResult::None,
+        };
+    }
+    match keydown_handler(keycodes, &mut select.menu, keymap) {
+        MenuResult::None => SlotResult::None,
+        MenuResult::Updated => SlotResult::Updated,
+        MenuResult::Selected(NEW_SLOT) => SlotResult::NewSlot,
+        MenuResult::Selected(_) => match select.selected_slot() {
+            Some(slot) => SlotResult::Selected(slot.clone()),
+            None => SlotResult::None,
+        },
+        MenuResult::Back => SlotResult::Back,
+    }
+}
+
+/// Slot names become file names, so they are kept to lowercase letters,
+/// digits and dashes
+pub fn name_entry_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    input: &mut String,
+) -> NameEntryResult {
+    for keycode in keycodes {
+        let c = match keycode {
+            VirtualKeyCode::Back => {
+                input.pop();
+                return NameEntryResult::Updated;
+            }
+            VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+                if input.is_empty() {
+                    return NameEntryResult::None;
+                }
+                return NameEntryResult::Done(input.clone());
+            }
+            VirtualKeyCode::Escape => return NameEntryResult::Back,
+            VirtualKeyCode::Minus => '-',
+            // Letters are named after themselves, and the digit keys Key0
+            // to Key9
+            _ => match keymap::key_name(keycode).as_str() {
+                letter if letter.len() == 1 => letter.to_ascii_lowercase().chars().next().unwrap(),
+                digit if digit.len() == 4 && digit.starts_with("Key") => {
+                    digit.chars().last().unwrap()
+                }
+                _ => continue,
+            },
+        };
+        if input.len() < MAX_SLOT_NAME_LENGTH {
+            input.push(c);
+            return NameEntryResult::Updated;
+        }
+    }
+    NameEntryResult::None
+}
+
+pub fn confirm_keydown_handler(keycodes: &HashSet<VirtualKeyCode>) -> ConfirmResult {
+    if keycodes.contains(&VirtualKeyCode::Y) {
+        ConfirmResult::Yes
+    } else if keycodes.contains(&VirtualKeyCode::N) || keycodes.contains(&VirtualKeyCode::Escape) {
+        ConfirmResult::No
+    } else {
+        ConfirmResult::None
+    }
+}
+
+pub fn options_keydown_handler(
+    keycodes: &HashSet<VirtualKeyCode>,
+    options: &mut Options,
+    keymap: &Keymap,
+) -> OptionsResult {
+    if options.rebinding {
+        let Some(&key) = keycodes.iter().next() else {
+            return OptionsResult::None;
+        };
+        options.message = None;
+        if key != VirtualKeyCode::Escape {
+            if let Some(action) = options.selected_action() {
+                if let Err(other) = options.keymap.bind(action, key) {
+                    options.message = Some(format!(
+                        "That is the only key for {}, which needs one",
+                        other.label()
+                    ));
+                }
+                options.refresh_menu();
+            }
+        }
+        options.rebinding = false;
+        return OptionsResult::Updated;
+    }
+    if keycodes.contains(&VirtualKeyCode::Back) {
+        if let Some(action) = options.selected_action() {
+            options.message = options
+                .keymap
+                .clear(action)
+                .err()
+                .map(|action| format!("{} always needs a key", action.label()));
+            options.refresh_menu();
+            return OptionsResult::Updated;
+        }
+    }
+    if !keycodes.is_empty() {
+        options.message = None;
+    }
+    match keydown_handler(keycodes, &mut options.menu, keymap) {
+        MenuResult::None => OptionsResult::None,
+        MenuResult::Updated => OptionsResult::Updated,
+        MenuResult::Selected(RESET_KEYS) => {
+            options.keymap = Keymap::default();
+            options.refresh_menu();
+            OptionsResult::Updated
+        }
+        MenuResult::Selected(SAVE_KEYS) => match options.keymap.unbound_required() {
+            Some(action) => {
+                options.message = Some(format!(
+                    "{} needs a key before the keys can be saved",
+                    action.label()
+                ));
+                OptionsResult::Updated
+            }
+            None => OptionsResult::Save,
+        },
+        MenuResult::Selected(_) => {
+            options.rebinding = true;
+            OptionsResult::Updated
+        }
+        MenuResult::Back => OptionsResult::Back,
+    }
+}
+
+pub fn game_over_keydown_handler(keycode: &HashSet<VirtualKeyCode>, mode: &mut GameMode) {
+    if !keycode.is_empty() {
+        *mode = GameMode::MainMenu(main_menu())
+    }
+}
+
+/// Splits text into lines no longer than `width`, breaking between words
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    for word in text.split_whitespace() {
+        let line = lines.last_mut().unwrap();
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(word.to_string());
+        } else {
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(word);
+        }
+    }
+    lines
+}
+
+#[derive(Debug, Default)]
+pub struct Renderer {
+    dirty: bool,
+    /// Shown under the main menu, for problems that don't stop play
+    notice: Option<String>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Renderer {
+            dirty: true,
+            notice: None,
+        }
+    }
+
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn set_notice(&mut self, notice: Option<String>) {
+        self.notice = notice;
+        self.mark_dirty();
+    }
+
+    pub fn draw(
+        &mut self,
+        gamemode: &GameMode,
+        console: &mut Console,
+        ctx: &mut ggez::Context,
+    ) -> anyhow::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        console.cls(ctx);
+        match gamemode {
+            GameMode::MainMenu(menu) => {
+                Self::draw_menu(menu, console);
+                if let Some(notice) = &self.notice {
+                    let lines = wrap(notice, CONSOLE_WIDTH as usize - 2);
+                    let top = CONSOLE_HEIGHT - 1 - lines.len() as i64;
+                    for (i, line) in lines.iter().enumerate() {
+                        console.print(
+                            ConsolePoint {
+                                x: 1,
+                                y: top + i as i64,
+                            },
+                            line,
+                        );
+                    }
+                }
+            }
+            GameMode::SeedEntry(input) => {
+                console.print(
+                    ConsolePoint { x: 1, y: 1 },
+                    "Enter a seed (enter: start, esc: back)",
+                );
+                console.print(ConsolePoint { x: 1, y: 3 }, &format!("> {}_", input));
+            }
+            GameMode::SlotSelect(select) => {
+                let title = match select.purpose {
+                    SlotPurpose::NewGame(_) => "Choose a slot for the new game",
+                    SlotPurpose::Load => "Load Game",
+                    SlotPurpose::Replay => "Watch Replay",
+                };
+                console.print(
+                    ConsolePoint { x: 1, y: 0 },
+                    &format!("{} (enter: select, del: delete, esc: back)", title),
+                );
+                Self::draw_menu(&select.menu, console);
+            }
+            GameMode::SlotNameEntry { input, .. } => {
+                console.print(
+                    ConsolePoint { x: 1, y: 1 },
+                    "Name the new slot (enter: start, esc: back)",
+                );
+                console.print(ConsolePoint { x: 1, y: 3 }, &format!("> {}_", input));
+            }
+            GameMode::Confirm(confirm) => {
+                console.print(
+                    ConsolePoint { x: 1, y: 1 },
+                    &format!("{} (y/n)", confirm.prompt),
+                );
+            }
+            GameMode::LoadFailed(failed) => {
+                console.print(
+                    ConsolePoint { x: 1, y: 1 },
+                    &format!("Couldn't open {} (any key: back)", failed.name),
+                );
+                Self::draw_causes(&failed.causes, console);
+            }
+            GameMode::Error(screen) => {
+                let title = if screen.previous.game().is_some() {
+                    "Something went wrong. The last command has been undone."
+                } else {
+                    "Something went wrong."
+                };
+                console.print(ConsolePoint { x: 1, y: 0 }, title);
+                Self::draw_menu(&screen.menu, console);
+                Self::draw_causes(&screen.causes, console);
+            }
+            GameMode::InGame {
+                game,
+                selected_point,
+            } => {
+                Self::draw_game(game, console)?;
+                let status = match selected_point {
+                    Some(pos) => format!("({:<2}, {:<2})", pos.x, pos.y),
+                    None => "Click something!".to_string(),
+                };
+                console.print(
+                    ConsolePoint {
+                        x: 0,
+                        y: WORLD_HEIGHT + 2,
+                    },
+                    &status,
+                );
+            }
+            GameMode::Replay(replay) => {
+                Self::draw_game(&replay.game, console)?;
+                let status = if replay.finished {
+                    "Replay finished (esc: back)".to_string()
+                } else {
+                    format!(
+                        "Replay at {} turns/s (+/-: speed, esc: back)",
+                        replay.turns_per_second
+                    )
+                };
+                console.print(
+                    ConsolePoint {
+                        x: 0,
+                        y: WORLD_HEIGHT + 2,
+                    },
+                    &status,
+                );
+                if let Some(turn) = replay.diverged_at {
+                    console.print(
+                        ConsolePoint {
+                            x: 0,
+                            y: WORLD_HEIGHT + 3,
+                        },
+                        &format!("Diverged from the recording at turn {}", turn),
+                    );
+                }
+            }
+            GameMode::Inventory { menu, .. } => {
+                console.print(
+                    ConsolePoint { x: 1, y: 0 },
+                    "Inventory (enter: use/equip, d: drop, esc: back)",
+                );
+                Self::draw_menu(menu, console);
+            }
+            GameMode::Options(options) => {
+                let title = match options.selected_action() {
+                    Some(action) if options.rebinding => {
+                        format!("Press a key for {} (esc: cancel)", action.label())
+                    }
+                    _ => "Keys (enter: add key, backspace: clear, esc: back)".to_string(),
+                };
+                console.print(ConsolePoint { x: 1, y: 0 }, &title);
+                Self::draw_menu(&options.menu, console);
+                if let Some(message) = &options.message {
+                    console.print(
+                        ConsolePoint {
+                            x: 1,
+                            y: CONSOLE_HEIGHT - 2,
+                        },
+                        message,
+                    );
+                }
+            }
+            GameMode::WonGame => {
+                console.cls(ctx);
+                console.print(ConsolePoint { x: 1, y: 1 }, "You Win");
+            }
+            GameMode::Dead(summary) => {
+                console.cls(ctx);
+                console.print(ConsolePoint { x: 1, y: 1 }, "You Died");
+                for (i, line) in summary.lines().iter().enumerate() {
+                    console.print(
+                        ConsolePoint {
+                            x: 1,
+                            y: 3 + i as i64,
+                        },
+                        line,
+                    );
+                }
+            }
+            GameMode::Transitioning => {}
+        }
+        console.finish(ctx)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Lists an error and everything that led to it, below whatever else
+    /// is on the screen
+    fn draw_causes(causes: &[String], console: &mut Console) {
+        let lines = causes
+            .iter()
+            .flat_map(|cause| wrap(cause, CONSOLE_WIDTH as usize - 4));
+        for (i, line) in lines.enumerate() {
+            console.print(
+                ConsolePoint {
+                    x: 3,
+                    y: 5 + i as i64,
+                },
+                &line,
+            );
+        }
+    }
+
+    fn draw_game(game: &Game, console: &mut Console) -> rusqlite::Result<()> {
+        let db = &game.db;
+        let remembered = component::vision::get_remembered(db)?;
+        Self::draw_remembered(&remembered, console);
+        let visible_actors = component::actor::get_visible(db)?;
+        Self::draw_actors(&visible_actors, console);
+        let turn = component::player::turns_passed(db)?;
+        console.print(ConsolePoint { x: 0, y: 0 }, &turn.to_string());
+        let depth = component::player::depth(db)?;
+        console.print(ConsolePoint { x: 10, y: 0 }, &format!("Depth {}", depth));
+        if let Some((current, max)) = component::health::get(db, game.player)? {
+            console.print(
+                ConsolePoint { x: 20, y: 0 },
+                &format!("HP {}/{}", current, max),
+            );
+        }
+        Ok(())
+    }
+
+    fn draw_actors(actors: &Vec<component::actor::Actor>, console: &mut Console) {
+        for actor in actors {
+            console.print_color(
+                actor.pos.into(),
+                actor.color,
+                game_object::BACKGROUND_COLOR,
+                &actor.tile,
+            );
+        }
+    }
+
+    fn draw_remembered(tiles: &Vec<(game_object::WorldPoint, String)>, console: &mut Console) {
+        for (pos, tile) in tiles {
+            console.print_color(
+                (*pos).into(),
+                game_object::REMEMBERED_COLOR,
+                game_object::BACKGROUND_COLOR,
+                tile,
+            );
+        }
+    }
+
+    fn draw_menu(menu: &Menu, console: &mut Console) {
+        for (i, item) in menu.items.iter().enumerate() {
+            let color = if i == menu.selected {
+                game_object::MENU_COLOR_SELECTED
+            } else {
+                game_object::MENU_COLOR_UNSELECTED
+            };
+            console.print_color(menu.top_left.down(i as i64), color.fg, color.bg, item)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Menu {
+    top_left: console::ConsolePoint,
+    selected: usize,
+    items: Arc<Vec<String>>,
+}
+
+pub enum MenuResult<'a> {
+    None,
+    Updated,
+    Selected(&'a str),
+    Back,
+}
+
+pub fn keydown_handler<'a>(
+    keycodes: &HashSet<VirtualKeyCode>,
+    menu: &'a mut Menu,
+    keymap: &Keymap,
+) -> MenuResult<'a> {
+    match keymap.action(keycodes, keymap::MENU_ACTIONS) {
+        Some(Action::MenuUp) => {
+            menu.add(-1);
+            MenuResult::Updated
+        }
+        Some(Action::MenuDown) => {
+            menu.add(1);
+            MenuResult::Updated
+        }
+        Some(Action::MenuSelect) => MenuResult::Selected(&menu.items[menu.selected]),
+        Some(Action::MenuBack) => MenuResult::Back,
+        _ => MenuResult::None,
+    }
+}
+
+pub fn main_menu() -> Menu {
+    static MAIN_MENU_ITEMS: LazyLock<Arc<Vec<String>>> = LazyLock::new(|| {
+        Arc::new(vec![
+            CREATIVE_MODE.to_string(),
+            NEW_GAME.to_string(),
+            NEW_SEEDED_GAME.to_string(),
+            LOAD_GAME.to_string(),
+            WATCH_REPLAY.to_string(),
+            OPTIONS.to_string(),
+        ])
+    });
+    Menu {
+        top_left: ConsolePoint { x: 0, y: 0 },
+        selected: 0,
+        items: MAIN_MENU_ITEMS.clone(),
+    }
+}
+
+impl Menu {
+    pub fn new(top_left: ConsolePoint, items: Vec<String>) -> Self {
+        Menu {
+            top_left,
+            selected: 0,
+            items: Arc::new(items),
+        }
+    }
+
+    pub fn add(&mut self, i: i64) {
+        self.selected = (self.selected as i64 + i).rem_euclid(self.items.len() as i64) as usize
+    }
+}